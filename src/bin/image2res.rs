@@ -23,70 +23,34 @@
 // (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
 // OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-use std::fmt::{Display, Formatter};
-use std::fs::File;
-use std::io;
-use std::io::Write;
+use std::fs::{self, File};
+use std::io::{BufWriter, Cursor, Write};
 use std::path::PathBuf;
 use clap::*;
-use image::{GenericImageView, ImageError, Pixel};
 use image::io::Reader as ImageReader;
+use pbtools::bitmap::Bitmap;
+use pbtools::palette::Palette;
+use pbtools::{indexed, ThemeError};
 
-enum ConvertError {
-    IO(std::io::Error),
-    Format(String),
-    Image(ImageError),
-}
-
-impl Display for ConvertError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ConvertError::IO(e) => writeln!(f, "I/O error: {}", e),
-            ConvertError::Format(s) => writeln!(f, "Bad format: {}", s),
-            ConvertError::Image(e) => writeln!(f, "Image save error: {}", e),
+fn convert(src: &PathBuf, palette: Option<&Palette>) -> Result<(), ThemeError> {
+    // PNGs of 8bpp bitmaps keep their colormap indexes, other images are decoded
+    let bytes = fs::read(src)?;
+    let bitmap = match indexed::read_png(&bytes, palette.unwrap_or(&Palette::Builtin))? {
+        Some(bitmap) => bitmap,
+        None => {
+            let img = ImageReader::new(Cursor::new(&bytes)).with_guessed_format()?.decode()?;
+            match palette {
+                Some(palette) => Bitmap::from_image_with_palette(&img, palette)?,
+                None => Bitmap::from_image(&img)?,
+            }
         }
-    }
-}
-
-impl From<io::Error> for ConvertError {
-    fn from(error: io::Error) -> Self {
-        ConvertError::IO(error)
-    }
-}
-
-impl From<ImageError> for ConvertError {
-    fn from(error: ImageError) -> Self {
-        ConvertError::Image(error)
-    }
-}
-
-fn convert(src: &PathBuf) -> Result<(),ConvertError> {
-    let img = ImageReader::open(src)?.decode()?;
+    };
     let mut dst = src.clone();
     dst.set_extension("");
 
-    let mut file = File::create(dst)?;
-    let (w, h) = img.dimensions();
-    if w > 0x7fff || h > 0x7fff {
-        return Err(ConvertError::Format(String::from("too big")));
-    }
-    let w16 = w as u16;
-    let h16 = h as u16;
-    file.write_all(&w16.to_le_bytes())?;
-    file.write_all(&h16.to_le_bytes())?;
-    file.write_all(&24u16.to_le_bytes())?;
-    let scanlinew = w16 * 3;
-    file.write_all(&scanlinew.to_le_bytes())?;
-    let mut scanline = vec![0u8; scanlinew as usize];
-    for y in 0..h {
-        for x in 0..w {
-            let rgb = img.get_pixel(x, y);
-            scanline[x as usize * 3 + 0] = rgb.channels()[0];
-            scanline[x as usize * 3 + 1] = rgb.channels()[1];
-            scanline[x as usize * 3 + 2] = rgb.channels()[2];
-        }
-        file.write_all(&scanline)?;
-    }
+    let mut file = BufWriter::new(File::create(dst)?);
+    bitmap.write(&mut file)?;
+    file.flush()?;
     Ok(())
 }
 
@@ -95,6 +59,12 @@ fn main() {
         .about("Convert images into PocketBook theme image resources")
         .author(crate_authors!("\n"))
         .version(crate_version!())
+        .arg(
+            Arg::new("palette")
+                .long("palette")
+                .value_name("PALETTE")
+                .help("Make 8bpp resources with the nearest colours from gray, builtin, or a .gpl, .act or .json palette file; PNGs of 8bpp resources are 8bpp again with builtin colours unless this says otherwise"),
+        )
         .arg(
             Arg::new("resource-file")
                 .value_parser(value_parser!(PathBuf))
@@ -102,8 +72,17 @@ fn main() {
         )
         .get_matches();
 
-    match convert(args.get_one("resource-file").unwrap()) {
-        Ok(_) => std::process::exit(0),
-        Err(e) => eprintln!("Failed {}", e),
+    let palette = match args.get_one::<String>("palette").map(|arg| Palette::from_arg(arg)) {
+        Some(Ok(palette)) => Some(palette),
+        Some(Err(e)) => {
+            eprintln!("Failed {}", e);
+            std::process::exit(e.exit_code());
+        }
+        None => None,
+    };
+    let src: &PathBuf = args.get_one("resource-file").unwrap();
+    if let Err(e) = convert(src, palette.as_ref()) {
+        eprintln!("Failed {}: {}", src.display(), e);
+        std::process::exit(e.exit_code());
     }
 }
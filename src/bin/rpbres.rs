@@ -23,334 +23,931 @@
 // (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
 // OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-use std::fmt::{Display, Formatter};
-use std::fs::File;
-use std::io::{self, BufReader, BufWriter, Read, Seek, Write};
-use std::path::PathBuf;
-
-use clap;
-use clap::{command, crate_authors, crate_version, value_parser, Arg, Command};
-use yazi::*;
-
-/// Application error handling
-enum ThemeError {
-    IO(io::Error),
-    Format(String),
-    Zlib(yazi::Error),
-}
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 
-impl Display for ThemeError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ThemeError::IO(e) => writeln!(f, "I/O error: {}", e),
-            ThemeError::Format(s) => writeln!(f, "Bad format: {}", s),
-            ThemeError::Zlib(e) => writeln!(f, "Decompress: {:?}", e),
-        }
-    }
-}
+use clap::{command, crate_authors, crate_version, value_parser, Arg, ArgAction, ArgMatches, Command};
+use pbtools::extract::{self, Scheme, CONFIG_FILENAME};
+use pbtools::kind::{KIND_NAMES, KIND_PREFIX_LEN};
+use pbtools::error::EXIT_PROBLEMS;
+use pbtools::listing::{self, Entry, Hash, SortKey, Template};
+use pbtools::manifest::{self, ManifestEntry, MANIFEST_FILENAME};
+use pbtools::parallel;
+use pbtools::select::Selection;
+use pbtools::theme::{inflate, read_compressed_resource_at};
+use pbtools::verify::verify;
+use pbtools::{Manifest, ResourceKind, Theme, ThemeError, ThemeWriter};
+use yazi::CompressionLevel;
 
-impl From<io::Error> for ThemeError {
-    fn from(error: io::Error) -> Self {
-        ThemeError::IO(error)
-    }
-}
 
-struct ResourceHeader {
-    size: u32,            // offset 0
-    offset: u32,          // offset 4
-    compressed_size: u32, // offset 8
-    name: String,         // offset 12
-                          // extra NUL bytes padding to the nearest 4 bytes
+/// How `list` writes the resources
+enum ListFormat {
+    Table,
+    Json,
+    Csv,
+    Tsv,
+    Template(Template),
 }
 
-enum ResourceKind {
-    Config(String),
-    Bitmap(String),
-    TrueType(String),
-    OpenType(String),
-    Json(String),
-    Unknown(String),
+/// What `list` shows, and in what order
+struct ListOptions {
+    format: ListFormat,
+    verbose: bool,
+    hash: Option<Hash>,
+    sort: Option<SortKey>,
+    reverse: bool,
+    kinds: Vec<String>,
+    min_size: u32,
 }
 
-impl ResourceKind {
-    pub fn kind_of(header: &ResourceHeader, bytes: &[u8]) -> ResourceKind {
-        if header.name.is_empty() {
-            return ResourceKind::Config(String::from("Configuration"));
-        }
-        if bytes.len() > 8 {
-            let w = u16::from_le_bytes([bytes[0], bytes[1]]);
-            let h = u16::from_le_bytes([bytes[2], bytes[3]]);
-            let rawbpp = u16::from_le_bytes([bytes[4], bytes[5]]);
-            let bpp = rawbpp & 0x7fff;
-            let transparent = if rawbpp >= 0x8000 {
-                " *"
-            } else {
-                ""
-            };
-            if w > 0 && w < 4096 && h > 0 && h < 4096 && bpp > 0 && bpp < 256 {
-                return ResourceKind::Bitmap(format!("Bitmap {} x {} {}bpp{}", w, h, bpp, transparent));
-            }
-        }
-        if bytes.len() > 2 {
-            if bytes.starts_with("{\"".as_bytes()) {
-                return ResourceKind::Json(String::from("JSON?"));
-            }
+fn list(themefile: &PathBuf, options: &ListOptions) -> Result<(), ThemeError> {
+    let theme = Theme::open(themefile)?;
+    if theme.is_raw() {
+        eprintln!(
+            "Warning: {} has unknown format version {}, the resource list is a best guess",
+            themefile.display(),
+            theme.version()
+        );
+    }
+
+    // Read just enough of each resource to classify it, or all of it to
+    // hash it or check its kind, in file order
+    let mut entries: Vec<Option<Entry>> = theme.headers().iter().map(|_| None).collect();
+    let mut indices: Vec<usize> = (0..theme.headers().len()).collect();
+    indices.sort_by_key(|&i| theme.headers()[i].offset);
+    for i in indices {
+        let header = &theme.headers()[i];
+        let mut data = match options.hash {
+            Some(_) => theme.read(header).map(Vec::from),
+            None => theme.read_prefix(header, KIND_PREFIX_LEN),
         }
-        if bytes.len() > 4 {
-            if bytes.starts_with("OTTO".as_bytes()) {
-                return ResourceKind::OpenType(String::from("OpenType font?"));
-            }
+        .map_err(|e| e.with_theme(themefile))?;
+        if data.len() < header.size as usize && ResourceKind::kind_of(header, &data).is_guess() {
+            data = theme.read(header).map_err(|e| e.with_theme(themefile))?.into();
         }
-        if bytes.len() > 4 {
-            if bytes.starts_with(&[0x00, 0x01, 0x00, 0x00]) {
-                return ResourceKind::TrueType(String::from("TrueType font?"));
+        let mut entry = Entry::new(i, header, &data);
+        entry.hash = options.hash.map(|hash| hash.hex(&data));
+        entries[i] = Some(entry);
+    }
+    let mut entries: Vec<Entry> = entries
+        .into_iter()
+        .flatten()
+        .filter(|entry| options.kinds.is_empty() || options.kinds.iter().any(|kind| kind == entry.kind))
+        .filter(|entry| entry.size >= options.min_size)
+        .collect();
+    if let Some(sort) = options.sort {
+        entries.sort_by(|a, b| sort.compare(a, b));
+    }
+    if options.reverse {
+        entries.reverse();
+    }
+
+    let mut out = io::stdout().lock();
+    match &options.format {
+        ListFormat::Table => print_table(&entries, options.verbose, options.hash.is_some()),
+        ListFormat::Json => listing::write_json(&mut out, &entries)?,
+        ListFormat::Csv => listing::write_csv(&mut out, &entries)?,
+        ListFormat::Tsv => listing::write_tsv(&mut out, &entries)?,
+        ListFormat::Template(template) => {
+            for entry in &entries {
+                writeln!(out, "{}", template.render(entry))?;
             }
         }
-        ResourceKind::Unknown(String::from("Unknown"))
     }
+    Ok(())
+}
 
-    pub fn to_string(&self) -> &String {
-        match self {
-            ResourceKind::Config(str) => &str,
-            ResourceKind::Bitmap(str) => &str,
-            ResourceKind::TrueType(str) => &str,
-            ResourceKind::OpenType(str) => &str,
-            ResourceKind::Json(str) => &str,
-            ResourceKind::Unknown(str) => &str,
+/// A column of the `list` table
+struct Column {
+    title: &'static str,
+    right: bool,
+    values: Vec<String>,
+}
+
+impl Column {
+    fn new(title: &'static str, right: bool, entries: &[Entry], value: fn(&Entry) -> String) -> Self {
+        Column {
+            title,
+            right,
+            values: entries.iter().map(value).collect(),
         }
     }
+
+    fn width(&self) -> usize {
+        self.values
+            .iter()
+            .map(|value| value.chars().count())
+            .chain([self.title.len()])
+            .max()
+            .unwrap_or(0)
+    }
 }
 
-fn read_headers<R: Read + Seek>(reader: &mut R) -> Result<Vec<ResourceHeader>, ThemeError> {
-    let mut fingerprint = [0u8; 15];
-    reader.read_exact(&mut fingerprint)?;
-    if fingerprint != "PocketBookTheme".as_bytes() {
-        return Err(ThemeError::Format(String::from(
-            "File does not start PocketBookTheme",
-        )));
+/// Print resources as a table for people to read
+fn print_table(entries: &[Entry], verbose: bool, hash: bool) {
+    let mut columns = Vec::new();
+    if verbose {
+        columns.push(Column::new("index", true, entries, |entry| entry.index.to_string()));
+    }
+    columns.push(Column::new("resource", false, entries, |entry| entry.name.clone()));
+    if verbose {
+        columns.push(Column::new("offset", true, entries, |entry| entry.offset.to_string()));
     }
-    let mut version = [0u8; 1];
-    reader.read_exact(&mut version)?;
-    if version[0] != 1 {
-        return Err(ThemeError::Format(String::from("Not version 1")));
+    columns.push(Column::new("size", true, entries, |entry| entry.size.to_string()));
+    columns.push(Column::new("compressed size", true, entries, |entry| {
+        entry.compressed_size.to_string()
+    }));
+    if verbose {
+        columns.push(Column::new("ratio", true, entries, |entry| match entry.ratio {
+            Some(ratio) => format!("{:.1}%", ratio * 100.0),
+            None => String::from("-"),
+        }));
     }
-    let mut header_len = [0u8; 4];
-    reader.read_exact(&mut header_len)?;
-    let endpos = reader.stream_position().unwrap() + u32::from_le_bytes(header_len) as u64 - 32u64;
-    let mut headers = vec![];
-    while reader.stream_position().unwrap() < endpos {
-        let header = read_resource_header(reader, headers.is_empty())?;
-        headers.push(header);
+    if hash {
+        columns.push(Column::new("hash", false, entries, |entry| {
+            entry.hash.clone().unwrap_or_default()
+        }));
     }
+    // The description is last, so it is not padded
+    let verboses = Column::new("verbose", false, entries, |entry| entry.description.clone());
+    let widths: Vec<usize> = columns.iter().map(Column::width).collect();
 
-    Ok(headers)
+    let mut titles = String::new();
+    for (column, &width) in columns.iter().zip(&widths) {
+        push_cell(&mut titles, column.title, width, column.right);
+    }
+    println!("{}{}", titles, verboses.title);
+    println!("{}", "-".repeat(widths.iter().map(|width| width + 2).sum::<usize>() + verboses.width()));
+    for (i, verbose) in verboses.values.iter().enumerate() {
+        let mut line = String::new();
+        for (column, &width) in columns.iter().zip(&widths) {
+            push_cell(&mut line, &column.values[i], width, column.right);
+        }
+        println!("{}{}", line, verbose);
+    }
 }
 
-fn read_resource<R: Read + Seek>(
-    reader: &mut R,
-    header: &ResourceHeader,
-) -> Result<Box<[u8]>, ThemeError> {
-    reader.seek(io::SeekFrom::Start(header.offset as u64))?;
-    let mut compressed = vec![0u8; header.compressed_size as usize];
-    reader.read_exact(&mut compressed)?;
-    match decompress(&compressed, Format::Zlib) {
-        Ok((uncompressed, _)) => Ok(uncompressed.into_boxed_slice()),
-        Err(e) => Err(ThemeError::Zlib(e)),
+/// Add a padded table cell and the gap after it to a line
+fn push_cell(line: &mut String, value: &str, width: usize, right: bool) {
+    if right {
+        line.push_str(&format!("{:>width$}  ", value, width = width));
+    } else {
+        line.push_str(&format!("{:<width$}  ", value, width = width));
     }
 }
 
-fn read_resource_header<R: Read>(
-    reader: &mut R,
-    first: bool,
-) -> Result<ResourceHeader, ThemeError> {
-    let mut size = [0u8; 4];
-    let mut unknown = [0u8; 4];
-    let mut compressed_size = [0u8; 4];
-    reader.read_exact(&mut size)?;
-    reader.read_exact(&mut unknown)?;
-    reader.read_exact(&mut compressed_size)?;
-    let mut name = String::from("");
-    while !first {
-        let mut chars = [0u8; 4];
-        reader.read_exact(&mut chars)?;
-        if chars[0] == 0 {
-            break;
-        }
-        name.push(chars[0] as char);
-        if chars[1] == 0 {
-            break;
-        }
-        name.push(chars[1] as char);
-        if chars[2] == 0 {
-            break;
+/// Where `unpack` writes the resources it selects
+enum Output {
+    /// One file per resource in this directory
+    Dir(PathBuf),
+    /// A single resource to standard output
+    Stdout,
+}
+
+fn unpack(
+    themefile: &PathBuf,
+    selection: &Selection,
+    scheme: Scheme,
+    output: &Output,
+) -> Result<(), ThemeError> {
+    let theme = Theme::open(themefile)?;
+    let in_theme = |e: ThemeError| e.with_theme(themefile);
+
+    let unmatched = selection.unmatched(theme.resources().map(|res| res.name()));
+
+    let filenames = extract::filenames(theme.resources().map(|res| res.name()), scheme);
+    let mut selected = Vec::new();
+    for (res, filename) in theme.resources().zip(&filenames) {
+        if selection.matches_name(res.name()) {
+            if selection.has_kinds() {
+                let prefix = res.data_prefix(KIND_PREFIX_LEN).map_err(in_theme)?;
+                if !selection.matches_kind(&ResourceKind::kind_of(res.header(), &prefix)) {
+                    continue;
+                }
+            }
+            selected.push((res, filename));
         }
-        name.push(chars[2] as char);
-        if chars[3] == 0 {
-            break;
+    }
+
+    if !unmatched.is_empty() {
+        let patterns: Vec<String> = unmatched.iter().map(|pattern| format!("{:?}", pattern)).collect();
+        return Err(ThemeError::NotFound(format!("No resources match {}", patterns.join(", "))).with_theme(themefile));
+    }
+
+    let dir = match output {
+        Output::Stdout => {
+            if selected.len() != 1 {
+                return Err(ThemeError::Usage(format!(
+                    "-o - needs exactly one resource, but {} were selected",
+                    selected.len()
+                )));
+            }
+            let data = selected[0].0.data().map_err(in_theme)?;
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(&data)?;
+            stdout.flush()?;
+            return Ok(());
         }
-        name.push(chars[3] as char);
-    }
-    Ok(ResourceHeader {
-        size: u32::from_le_bytes(size),
-        offset: u32::from_le_bytes(unknown),
-        compressed_size: u32::from_le_bytes(compressed_size),
-        name: name,
-    })
+        Output::Dir(dir) => dir,
+    };
+
+    fs::create_dir_all(dir).map_err(|e| with_path(e, dir))?;
+    for (res, filename) in selected {
+        let data = res.data().map_err(in_theme)?;
+        report_renamed(res.name(), filename, scheme);
+        let path = extract::output_path(dir, filename).map_err(in_theme)?;
+        let mut file = BufWriter::new(File::create(&path).map_err(|e| with_path(e, &path))?);
+        file.write_all(&data)
+            .and_then(|_| file.flush())
+            .map_err(|e| with_path(e, &path))?;
+    }
+    Ok(())
 }
 
-fn list(themefile: &PathBuf) -> () {
-    let mut reader: BufReader<File> = match File::open(themefile) {
-        Err(why) => {
-            eprintln!("couldn't open {}: {}", themefile.display(), why);
-            return;
+/// Say which file an I/O error was about
+fn with_path(error: io::Error, path: &Path) -> io::Error {
+    io::Error::new(error.kind(), format!("{}: {}", path.display(), error))
+}
+
+/// Say when a resource is not extracted into a file of the same name
+///
+/// Percent encoding can be reversed, so it is not counted as renaming.
+fn report_renamed(name: &str, filename: &str, scheme: Scheme) {
+    let expected = match scheme {
+        Scheme::Plain => Cow::Borrowed(name),
+        Scheme::Percent => scheme.filename(name),
+    };
+    if !name.is_empty() && expected != filename {
+        eprintln!("Renamed {:?} to {:?}", name, filename);
+    }
+}
+
+/// Check a theme, returning whether it passed
+fn test(themefile: &PathBuf) -> Result<bool, ThemeError> {
+    let file = File::open(themefile).map_err(|e| ThemeError::from(e).with_theme(themefile))?;
+    let issues = verify(&mut BufReader::new(file)).map_err(|e| e.with_theme(themefile))?;
+    for issue in &issues {
+        match &issue.resource {
+            Some((_, name)) if name.is_empty() => {
+                println!("configuration: {}", issue.problem)
+            }
+            Some((i, name)) => println!("{} (#{}): {}", name, i, issue.problem),
+            None => println!("{}: {}", themefile.display(), issue.problem),
         }
-        Ok(file) => BufReader::new(file),
+    }
+    if issues.is_empty() {
+        println!("{}: OK", themefile.display());
+    } else {
+        let plural = if issues.len() == 1 { "" } else { "s" };
+        println!("{}: {} problem{}", themefile.display(), issues.len(), plural);
+    }
+    Ok(issues.is_empty())
+}
+
+fn extract(themefile: &PathBuf, dir: &PathBuf, scheme: Scheme) -> Result<(), ThemeError> {
+    let theme = Theme::open(themefile)?;
+    let headers = theme.headers().to_vec();
+    let file = theme.into_inner().into_inner();
+    fs::create_dir_all(dir).map_err(|e| with_path(e, dir))?;
+
+    let filenames = extract::filenames(headers.iter().map(|header| header.name.as_str()), scheme);
+    for (header, filename) in headers.iter().zip(&filenames) {
+        report_renamed(&header.name, filename, scheme);
+    }
+
+    let indexed: Vec<_> = headers.iter().zip(&filenames).collect();
+    let entries = parallel::map(&indexed, |&(header, filename)| {
+        let in_theme = |e: ThemeError| e.with_resource(header).with_theme(themefile);
+        let compressed = read_compressed_resource_at(&file, header).map_err(in_theme)?;
+        let data = inflate(&compressed).map_err(in_theme)?;
+        let path = extract::output_path(dir, filename).map_err(in_theme)?;
+        fs::write(&path, &data).map_err(|e| with_path(e, &path))?;
+        Ok(ManifestEntry {
+            name: header.name.clone(),
+            file: filename.clone(),
+            size: header.size,
+            offset: header.offset,
+            compressed_size: header.compressed_size,
+            level: manifest::zlib_level(&compressed),
+            sha256: manifest::sha256_hex(&data),
+            compressed_sha256: manifest::sha256_hex(&compressed),
+        })
+    });
+
+    let manifest = Manifest {
+        source: fs::canonicalize(themefile)
+            .ok()
+            .and_then(|path| path.into_os_string().into_string().ok()),
+        scheme,
+        resources: entries.into_iter().collect::<Result<_, ThemeError>>()?,
     };
+    manifest.save(dir.join(MANIFEST_FILENAME))
+}
 
-    match read_headers(&mut reader) {
-        Err(why) => {
-            eprintln!("Error: {}", why);
-            return;
+fn pack(
+    themefile: &PathBuf,
+    dir: &PathBuf,
+    base: Option<&PathBuf>,
+    scheme: Option<Scheme>,
+) -> Result<(), ThemeError> {
+    let in_dir = |e: io::Error| ThemeError::from(with_path(e, dir));
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).map_err(in_dir)? {
+        let entry = entry.map_err(in_dir)?;
+        if !entry.file_type().map_err(|e| with_path(e, &entry.path()))?.is_file() {
+            continue;
         }
-        Ok(headers) => {
-            // loop through first to find column widths
-            let mut verboses = Vec::new();
-            let mut max_resource = "resource".len();
-            let mut max_size = 9999usize;
-            let mut max_csize = 999999999999999usize;
-            let mut max_verbose = "verbose".len();
-            for header in &headers {
-                let res = match read_resource(&mut reader, header) {
-                    Ok(res) => res,
-                    Err(e) => panic!("Error {}", e),
-                };
-                let kind = ResourceKind::kind_of(header, &res);
-                verboses.push(kind.to_string().clone());
-                if header.name.len() > max_resource {
-                    max_resource = header.name.len();
-                }
-                if header.size as usize > max_size {
-                    max_size = header.size as usize;
+        match entry.file_name().into_string() {
+            Ok(name) if !name.starts_with('.') => files.push(name),
+            Ok(_) => {}
+            Err(name) => eprintln!("Skipping {:?}: not a valid resource name", name),
+        }
+    }
+    // A manifest from a full extraction gives the resource order, and lets
+    // unchanged resources reuse the compressed data from the original theme.
+    let manifest_path = dir.join(MANIFEST_FILENAME);
+    let manifest = if manifest_path.exists() {
+        Some(Manifest::load(&manifest_path).map_err(|e| e.with_theme(&manifest_path))?)
+    } else {
+        None
+    };
+    let scheme = scheme
+        .or(manifest.as_ref().map(|manifest| manifest.scheme))
+        .unwrap_or_else(Scheme::platform_default);
+    // Files the manifest does not know about are new resources; stock themes
+    // keep their resources sorted by name
+    let mut new_files: Vec<(String, String)> = files
+        .iter()
+        .filter(|file| match &manifest {
+            Some(manifest) => !manifest.resources.iter().any(|entry| entry.file == **file),
+            None => *file != CONFIG_FILENAME,
+        })
+        .map(|file| (scheme.name(file).into_owned(), file.clone()))
+        .collect();
+    new_files.sort();
+
+    let base = match base {
+        Some(base) => Some((Theme::open(base)?, base.clone())),
+        None => match manifest.as_ref().and_then(|manifest| manifest.source.as_ref()) {
+            Some(source) => match Theme::open(source) {
+                Ok(theme) => Some((theme, PathBuf::from(source))),
+                Err(e) => {
+                    // Packing still works, but nothing can be copied unchanged
+                    eprintln!(
+                        "Warning: every resource will be recompressed, as the original theme cannot be opened ({}); use --base to say where it is",
+                        e
+                    );
+                    None
                 }
-                if header.compressed_size as usize > max_csize {
-                    max_csize = header.compressed_size as usize;
+            },
+            None => None,
+        },
+    };
+
+    let mut resources = Vec::new();
+    match &manifest {
+        Some(manifest) => {
+            for entry in &manifest.resources {
+                if !files.contains(&entry.file) {
+                    return Err(ThemeError::NotFound(format!(
+                        "Missing {} for resource {:?}",
+                        entry.file, entry.name
+                    )));
                 }
-                if kind.to_string().len() > max_verbose {
-                    max_verbose = kind.to_string().len();
+                resources.push((entry.name.clone(), entry.file.clone()));
+            }
+        }
+        None => {
+            if !files.iter().any(|file| file == CONFIG_FILENAME) {
+                return Err(ThemeError::NotFound(format!(
+                    "No {} in {}",
+                    CONFIG_FILENAME,
+                    dir.display()
+                )));
+            }
+            resources.push((String::new(), String::from(CONFIG_FILENAME)));
+        }
+    }
+    resources.extend(new_files);
+
+    let mut writer = ThemeWriter::new();
+    let mut unchanged = manifest
+        .as_ref()
+        .is_some_and(|manifest| manifest.resources.len() == resources.len());
+    for (i, (name, file)) in resources.iter().enumerate() {
+        let path = dir.join(file);
+        let data = fs::read(&path).map_err(|e| with_path(e, &path))?;
+        let entry = manifest.as_ref().and_then(|manifest| manifest.entry(name));
+        let original = match (entry, &base) {
+            (Some(entry), Some((base, base_path))) if manifest::sha256_hex(&data) == entry.sha256 => {
+                match base.resource(name) {
+                    Some(res) => Some(res.compressed_data().map_err(|e| e.with_theme(base_path))?)
+                        .filter(|compressed| manifest::sha256_hex(compressed) == entry.compressed_sha256),
+                    None => None,
                 }
             }
-            // Loop through second time to print the table
-            max_size = digits_len(max_size);
-            max_csize = digits_len(max_csize);
-            println!("{:<rwidth$}  {:>swidth$}  {:>cwidth$}  {}",
-                     "resource",
-                     "size",
-                     "compressed size",
-                     "verbose",
-                     rwidth = max_resource, swidth = max_size, cwidth = max_csize,
-            );
-            println!("{}",
-                     "-".repeat(max_resource + 2 + max_size + 2 + max_csize + 2 + max_verbose));
-            let mut i = 0;
-            for header in &headers {
-                println!("{:<rwidth$}  {:>swidth$}  {:>cwidth$}  {}",
-                         header.name, header.size, header.compressed_size, verboses[i],
-                         rwidth = max_resource, swidth = max_size, cwidth = max_csize,
-                );
-                i = i + 1;
+            _ => None,
+        };
+        let level = entry.map_or(CompressionLevel::Default, |entry| {
+            manifest::compression_level(entry.level)
+        });
+        writer.level(level);
+        unchanged &= original.is_some();
+        match (i, original) {
+            (0, Some(compressed)) => writer.config_compressed(data.len() as u32, compressed)?,
+            (0, None) => writer.config(&data)?,
+            (_, Some(compressed)) => writer.add_compressed(name, data.len() as u32, compressed)?,
+            (_, None) => writer.add(name, &data)?,
+        };
+    }
+
+    // Data that is all unchanged goes back where it was, along with any gaps
+    // and trailing data; otherwise it follows the table in table order.
+    if let Some(manifest) = &manifest {
+        let offsets: Vec<u32> = manifest.resources.iter().map(|entry| entry.offset).collect();
+        match &base {
+            Some((base, base_path))
+                if unchanged
+                    && base.headers().iter().map(|header| header.offset).eq(offsets.iter().copied()) =>
+            {
+                let filler = fs::read(base_path).map_err(|e| with_path(e, base_path))?;
+                writer.layout(offsets, filler);
             }
+            _ if !manifest.is_contiguous() => eprintln!(
+                "Warning: {} will have its data in table order without gaps, unlike the original theme",
+                themefile.display()
+            ),
+            _ => {}
         }
     }
+
+    let in_theme = |e: ThemeError| e.with_theme(themefile);
+    let mut file = BufWriter::new(File::create(themefile).map_err(|e| in_theme(e.into()))?);
+    writer.write(&mut file).map_err(in_theme)?;
+    file.flush().map_err(|e| in_theme(e.into()))?;
+    Ok(())
 }
 
-fn digits_len(val: usize) -> usize {
-    let str = format!("{}", val);
-    str.len()
+/// A change to make to an existing theme
+enum Change {
+    Replace(String, Vec<u8>),
+    Add(String, Vec<u8>),
+    Delete(Vec<String>),
 }
 
-fn unpack(themefile: &PathBuf, resources: Vec<&String>) -> () {
-    let mut reader: BufReader<File> = match File::open(themefile) {
-        Err(why) => {
-            eprintln!("couldn't open {}: {}", themefile.display(), why);
-            return;
-        }
-        Ok(file) => BufReader::new(file),
-    };
+/// Resource data, either copied from the theme or new
+enum Payload {
+    Compressed(u32, Vec<u8>),
+    Raw(Vec<u8>),
+}
 
-    match read_headers(&mut reader) {
-        Err(why) => {
-            eprintln!("Error: {}", why);
-            return;
+/// Rewrite a theme in place with one change applied
+///
+/// Untouched resources are copied still compressed, so they stay byte-identical.
+fn edit(themefile: &PathBuf, change: Change) -> Result<(), ThemeError> {
+    let theme = Theme::open(themefile)?;
+    if theme.is_raw() {
+        return Err(ThemeError::Format(format!(
+            "Cannot rewrite a theme with unknown format version {}",
+            theme.version()
+        )));
+    }
+    let mut entries = Vec::new();
+    for res in theme.resources() {
+        let payload = Payload::Compressed(res.header().size, res.compressed_data()?);
+        entries.push((res.name().to_string(), payload));
+    }
+    drop(theme);
+    if entries.is_empty() {
+        return Err(ThemeError::Format(String::from("Theme has no configuration")));
+    }
+
+    let position = |entries: &Vec<(String, Payload)>, name: &str| {
+        entries.iter().position(|(n, _)| n == name)
+    };
+    match change {
+        Change::Replace(name, data) => match position(&entries, &name) {
+            Some(i) => entries[i].1 = Payload::Raw(data),
+            None => return Err(ThemeError::NotFound(format!("No resource {:?}", name))),
+        },
+        Change::Add(name, data) => {
+            if position(&entries, &name).is_some() {
+                return Err(ThemeError::Format(format!("Resource {:?} already exists", name)));
+            }
+            // Keep the table sorted if it already is, otherwise append
+            let names = &entries[1.min(entries.len())..];
+            let i = if names.windows(2).all(|w| w[0].0 <= w[1].0) {
+                1 + names.partition_point(|(n, _)| *n < name)
+            } else {
+                entries.len()
+            };
+            entries.insert(i.min(entries.len()), (name, Payload::Raw(data)));
         }
-        Ok(headers) => {
-            for header in &headers {
-                if resources.contains(&&header.name) {
-                    let res = match read_resource(&mut reader, header) {
-                        Ok(res) => res,
-                        Err(e) => {
-                            eprintln!("Error {}", e);
-                            return;
-                        }
-                    };
-                    let filename = if header.name.is_empty() {
-                        PathBuf::from("theme.cfg")
-                    } else {
-                        PathBuf::from(&header.name)
-                    };
-                    let mut file = match File::create(filename) {
-                        Err(e) => {
-                            eprintln!("Error creating file {}", e);
-                            return;
-                        }
-                        Ok(file) => BufWriter::new(file),
-                    };
-                    match file.write_all(&*res) {
-                        Err(e) => {
-                            eprintln!("Error writing file {}", e);
-                            return;
-                        }
-                        Ok(_) => { },
+        Change::Delete(names) => {
+            for name in names {
+                match position(&entries, &name) {
+                    Some(0) => {
+                        return Err(ThemeError::Format(String::from(
+                            "The theme configuration cannot be deleted",
+                        )))
                     }
+                    Some(i) => {
+                        entries.remove(i);
+                    }
+                    None => return Err(ThemeError::NotFound(format!("No resource {:?}", name))),
                 }
             }
         }
     }
+
+    let mut writer = ThemeWriter::new();
+    for (i, (name, payload)) in entries.into_iter().enumerate() {
+        match (i, payload) {
+            (0, Payload::Compressed(size, compressed)) => writer.config_compressed(size, compressed)?,
+            (0, Payload::Raw(data)) => writer.config(&data)?,
+            (_, Payload::Compressed(size, compressed)) => writer.add_compressed(&name, size, compressed)?,
+            (_, Payload::Raw(data)) => writer.add(&name, &data)?,
+        };
+    }
+
+    // Write alongside the original and only replace it once complete
+    let mut tmpfile = themefile.clone().into_os_string();
+    tmpfile.push(".tmp");
+    let tmpfile = PathBuf::from(tmpfile);
+    let result = File::create(&tmpfile)
+        .map_err(ThemeError::from)
+        .and_then(|file| {
+            let mut file = BufWriter::new(file);
+            writer.write(&mut file)?;
+            file.flush()?;
+            Ok(())
+        })
+        .and_then(|_| Ok(fs::rename(&tmpfile, themefile)?));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmpfile);
+    }
+    result
+}
+
+/// The option for subcommands that map resource names to file names
+fn names_arg() -> Arg {
+    Arg::new("names")
+        .long("names")
+        .value_parser(["plain", "percent"])
+        .help(
+            "How resource names map to file names: plain, or percent to encode \
+             characters such as : that some platforms do not allow \
+             [default: percent on Windows, otherwise plain]",
+        )
 }
 
 fn main() {
     let args = command!()
-        .about("List PocketBook themes and extract theme resources")
+        .about("List, extract, create and edit PocketBook themes")
         .author(crate_authors!("\n"))
         .version(crate_version!())
         .subcommand(
-            Command::new("-l").about("List theme resources").arg(
-                Arg::new("theme-file")
-                    .value_parser(value_parser!(PathBuf))
-                    .required(true),
-            ),
+            Command::new("-l")
+                .alias("list")
+                .about("List theme resources")
+                .arg(
+                    Arg::new("theme-file")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(["table", "json", "csv", "tsv"])
+                        .default_value("table")
+                        .help("Output format"),
+                )
+                .arg(
+                    Arg::new("template")
+                        .long("template")
+                        .conflicts_with("format")
+                        .help(format!(
+                            "Write a line per resource from a template such as '{{name}}\\t{{width}}x{{height}}'; \
+                             the fields are {}",
+                            listing::FIELDS.join(", ")
+                        )),
+                )
+                .arg(
+                    Arg::new("verbose")
+                        .short('v')
+                        .long("verbose")
+                        .action(ArgAction::SetTrue)
+                        .help("Also show each resource's index, offset and compression ratio"),
+                )
+                .arg(
+                    Arg::new("hash")
+                        .long("hash")
+                        .value_parser(["sha256", "crc32"])
+                        .help("Show a hash of each resource's uncompressed data"),
+                )
+                .arg(
+                    Arg::new("sort")
+                        .long("sort")
+                        .value_parser(["size", "csize", "ratio", "name", "offset"])
+                        .help("Sort resources, smallest first, instead of in table order"),
+                )
+                .arg(
+                    Arg::new("reverse")
+                        .long("reverse")
+                        .action(ArgAction::SetTrue)
+                        .help("Reverse the order of the list"),
+                )
+                .arg(
+                    Arg::new("kind")
+                        .long("kind")
+                        .value_parser(KIND_NAMES)
+                        .action(ArgAction::Append)
+                        .help("Only list resources of this kind; may be given more than once"),
+                )
+                .arg(
+                    Arg::new("min-size")
+                        .long("min-size")
+                        .value_name("BYTES")
+                        .value_parser(value_parser!(u32))
+                        .help("Only list resources at least this big when uncompressed"),
+                ),
         )
         .subcommand(
             Command::new("-u")
+                .alias("unpack")
                 .about("Unpack theme resources")
                 .arg(
                     Arg::new("theme-file")
                         .value_parser(value_parser!(PathBuf))
                         .required(true),
                 )
+                .arg(
+                    Arg::new("resource-name")
+                        .required_unless_present("kind")
+                        .num_args(1..)
+                        .help("Resource names, or glob patterns such as 'arrow_*' or '*:4'"),
+                )
+                .arg(
+                    Arg::new("regex")
+                        .long("regex")
+                        .action(ArgAction::SetTrue)
+                        .help("Match resource names with regular expressions instead of globs"),
+                )
+                .arg(
+                    Arg::new("kind")
+                        .long("kind")
+                        .value_parser(KIND_NAMES)
+                        .action(ArgAction::Append)
+                        .help("Only unpack resources of this kind; may be given more than once"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("DIR")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Directory to unpack into, or - to write a single resource to standard output"),
+                )
+                .arg(names_arg()),
+        )
+        .subcommand(
+            Command::new("-c")
+                .alias("pack")
+                .about("Create a theme from a directory of unpacked resources")
+                .long_about(
+                    "Create a theme from a directory of unpacked resources. \
+                     theme.cfg becomes the theme configuration, every other file \
+                     becomes a resource named after the file. Hidden files are ignored.\n\n\
+                     If the directory has a manifest from -x, the resources are packed \
+                     in the original order, and unchanged resources reuse the compressed \
+                     data from the original theme.",
+                )
+                .arg(
+                    Arg::new("theme-file")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("directory")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("base")
+                        .long("base")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Original theme to take unchanged resources from, instead of the manifest's"),
+                )
+                .arg(names_arg()),
+        )
+        .subcommand(
+            Command::new("-t")
+                .alias("test")
+                .about("Check the structure of a theme and all of its resources")
+                .arg(
+                    Arg::new("theme-file")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("-x")
+                .alias("extract")
+                .about("Extract all theme resources and a manifest into a directory")
+                .arg(
+                    Arg::new("theme-file")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("directory")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true),
+                )
+                .arg(names_arg()),
+        )
+        .subcommand(
+            Command::new("-r")
+                .alias("replace")
+                .about("Replace a theme resource with the contents of a file")
+                .arg(
+                    Arg::new("theme-file")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .num_args(1..=2)
+                        .value_names(["resource-name", "file"])
+                        .help("Resource name and file, or just a file named as -u or -x would name it"),
+                )
+                .arg(names_arg()),
+        )
+        .subcommand(
+            Command::new("-a")
+                .alias("add")
+                .about("Add a new theme resource from the contents of a file")
+                .arg(
+                    Arg::new("theme-file")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .num_args(1..=2)
+                        .value_names(["resource-name", "file"])
+                        .help("Resource name and file, or just a file named as -u or -x would name it"),
+                )
+                .arg(names_arg()),
+        )
+        .subcommand(
+            Command::new("-d")
+                .alias("delete")
+                .about("Delete theme resources")
+                .arg(
+                    Arg::new("theme-file")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true),
+                )
                 .arg(Arg::new("resource-name").required(true).num_args(1..)),
         )
         .disable_help_subcommand(true)
         .get_matches();
 
-    if let Some(list_args) = args.subcommand_matches("-l") {
-        list(list_args.get_one::<PathBuf>("theme-file").unwrap());
+    let names_of = |args: &ArgMatches| {
+        args.get_one::<String>("names")
+            .map(|names| names.parse::<Scheme>().unwrap())
+    };
+    let scheme_of = |args: &ArgMatches| names_of(args).unwrap_or_else(Scheme::platform_default);
+
+    let result = if let Some(list_args) = args.subcommand_matches("-l") {
+        let format = match list_args.get_one::<String>("template") {
+            Some(template) => Template::parse(template).map(ListFormat::Template),
+            None => Ok(match list_args.get_one::<String>("format").unwrap().as_str() {
+                "json" => ListFormat::Json,
+                "csv" => ListFormat::Csv,
+                "tsv" => ListFormat::Tsv,
+                _ => ListFormat::Table,
+            }),
+        };
+        format.and_then(|format| {
+            let options = ListOptions {
+                format,
+                verbose: list_args.get_flag("verbose"),
+                hash: list_args.get_one::<String>("hash").map(|hash| match hash.as_str() {
+                    "crc32" => Hash::Crc32,
+                    _ => Hash::Sha256,
+                }),
+                sort: list_args.get_one::<String>("sort").map(|sort| match sort.as_str() {
+                    "size" => SortKey::Size,
+                    "csize" => SortKey::CompressedSize,
+                    "ratio" => SortKey::Ratio,
+                    "offset" => SortKey::Offset,
+                    _ => SortKey::Name,
+                }),
+                reverse: list_args.get_flag("reverse"),
+                kinds: list_args.get_many::<String>("kind").into_iter().flatten().cloned().collect(),
+                min_size: *list_args.get_one::<u32>("min-size").unwrap_or(&0),
+            };
+            list(list_args.get_one::<PathBuf>("theme-file").unwrap(), &options)
+        })
     } else if let Some(unpack_args) = args.subcommand_matches("-u") {
-        unpack(
-            unpack_args.get_one::<PathBuf>("theme-file").unwrap(),
-            unpack_args.get_many::<String>("resource-name")
-                .into_iter()
-                .flatten()
-                .collect::<Vec<_>>(),
-        );
+        let mut selection = Selection::new();
+        let mut result = Ok(());
+        for pattern in unpack_args.get_many::<String>("resource-name").into_iter().flatten() {
+            let added = if unpack_args.get_flag("regex") {
+                selection.regex(pattern)
+            } else {
+                selection.glob(pattern)
+            };
+            if let Err(e) = added {
+                result = Err(e);
+                break;
+            }
+        }
+        for kind in unpack_args.get_many::<String>("kind").into_iter().flatten() {
+            selection.kind(kind);
+        }
+        let output = match unpack_args.get_one::<PathBuf>("output") {
+            Some(path) if path.as_os_str() == "-" => Output::Stdout,
+            Some(path) => Output::Dir(path.clone()),
+            None => Output::Dir(PathBuf::from(".")),
+        };
+        result.and_then(|_| {
+            unpack(
+                unpack_args.get_one::<PathBuf>("theme-file").unwrap(),
+                &selection,
+                scheme_of(unpack_args),
+                &output,
+            )
+        })
+    } else if let Some(pack_args) = args.subcommand_matches("-c") {
+        pack(
+            pack_args.get_one::<PathBuf>("theme-file").unwrap(),
+            pack_args.get_one::<PathBuf>("directory").unwrap(),
+            pack_args.get_one::<PathBuf>("base"),
+            names_of(pack_args),
+        )
+    } else if let Some(test_args) = args.subcommand_matches("-t") {
+        match test(test_args.get_one::<PathBuf>("theme-file").unwrap()) {
+            Ok(false) => std::process::exit(EXIT_PROBLEMS),
+            result => result.map(|_| ()),
+        }
+    } else if let Some(extract_args) = args.subcommand_matches("-x") {
+        extract(
+            extract_args.get_one::<PathBuf>("theme-file").unwrap(),
+            extract_args.get_one::<PathBuf>("directory").unwrap(),
+            scheme_of(extract_args),
+        )
+    } else if let Some((name, edit_args)) = args.subcommand() {
+        let themefile = edit_args.get_one::<PathBuf>("theme-file").unwrap();
+        let change = match name {
+            "-r" | "-a" => {
+                let values: Vec<&String> = edit_args.get_many::<String>("file")
+                    .into_iter()
+                    .flatten()
+                    .collect();
+                let file = PathBuf::from(values[values.len() - 1]);
+                let resource = if values.len() == 2 {
+                    values[0].clone()
+                } else {
+                    // Unpacking gives the configuration this name in either scheme
+                    let filename = file.file_name().unwrap_or_default().to_string_lossy();
+                    if filename == CONFIG_FILENAME {
+                        String::new()
+                    } else {
+                        scheme_of(edit_args).name(&filename).into_owned()
+                    }
+                };
+                fs::read(&file)
+                    .map_err(|e| ThemeError::from(with_path(e, &file)))
+                    .map(|data| {
+                        if name == "-r" {
+                            Change::Replace(resource, data)
+                        } else {
+                            Change::Add(resource, data)
+                        }
+                    })
+            }
+            _ => Ok(Change::Delete(
+                edit_args.get_many::<String>("resource-name")
+                    .into_iter()
+                    .flatten()
+                    .cloned()
+                    .collect(),
+            )),
+        };
+        change.and_then(|change| edit(themefile, change).map_err(|e| e.with_theme(themefile)))
+    } else {
+        Ok(())
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error {}", e);
+        std::process::exit(e.exit_code());
     }
 }
@@ -0,0 +1,993 @@
+// Copyright 2024 Chris Ridd <chrisridd@mac.com>. All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use image::{DynamicImage, GenericImageView, GrayImage, Luma, Pixel, Rgb, RgbImage, Rgba, RgbaImage};
+
+use crate::error::ThemeError;
+use crate::palette::Palette;
+
+/// The builtin colormap of 8bpp bitmaps
+pub(crate) fn pixel_to_rgb(pixel: u8) -> Rgb<u8> {
+    match pixel {
+        0x00u8 => Rgb([0x01u8, 0x00u8, 0x03u8]),
+        0x01u8 => Rgb([0x02u8, 0x01u8, 0x07u8]),
+        0x02u8 => Rgb([0x03u8, 0x01u8, 0x0bu8]),
+        0x03u8 => Rgb([0x05u8, 0x02u8, 0x0fu8]),
+        0x04u8 => Rgb([0x06u8, 0x03u8, 0x13u8]),
+        0x05u8 => Rgb([0x07u8, 0x03u8, 0x18u8]),
+        0x06u8 => Rgb([0x09u8, 0x04u8, 0x1bu8]),
+        0x07u8 => Rgb([0x0au8, 0x05u8, 0x20u8]),
+        0x08u8 => Rgb([0x0bu8, 0x05u8, 0x24u8]),
+        0x09u8 => Rgb([0x0du8, 0x06u8, 0x28u8]),
+        0x0au8 => Rgb([0x0eu8, 0x07u8, 0x2cu8]),
+        0x0bu8 => Rgb([0x0fu8, 0x07u8, 0x30u8]),
+        0x0cu8 => Rgb([0x11u8, 0x08u8, 0x34u8]),
+        0x0du8 => Rgb([0x12u8, 0x09u8, 0x38u8]),
+        0x0eu8 => Rgb([0x13u8, 0x09u8, 0x3cu8]),
+        0x0fu8 => Rgb([0x15u8, 0x0au8, 0x40u8]),
+
+        0x10u8 => Rgb([0x16u8, 0x0bu8, 0x44u8]),
+        0x11u8 => Rgb([0x18u8, 0x0bu8, 0x48u8]),
+        0x12u8 => Rgb([0x19u8, 0x0cu8, 0x4cu8]),
+        0x13u8 => Rgb([0x1au8, 0x0du8, 0x51u8]),
+        0x14u8 => Rgb([0x1cu8, 0x0du8, 0x55u8]),
+        0x15u8 => Rgb([0x1du8, 0x0eu8, 0x59u8]),
+        0x16u8 => Rgb([0x1eu8, 0x0fu8, 0x5du8]),
+        0x17u8 => Rgb([0x20u8, 0x0fu8, 0x61u8]),
+        0x18u8 => Rgb([0x21u8, 0x10u8, 0x65u8]),
+        0x19u8 => Rgb([0x22u8, 0x11u8, 0x69u8]),
+        0x1au8 => Rgb([0x24u8, 0x11u8, 0x6du8]),
+        0x1bu8 => Rgb([0x25u8, 0x12u8, 0x6fu8]),
+        0x1cu8 => Rgb([0x27u8, 0x13u8, 0x72u8]),
+        0x1du8 => Rgb([0x28u8, 0x14u8, 0x74u8]),
+        0x1eu8 => Rgb([0x2au8, 0x14u8, 0x76u8]),
+        0x1fu8 => Rgb([0x2bu8, 0x15u8, 0x78u8]),
+
+        0x20u8 => Rgb([0x2du8, 0x16u8, 0x79u8]),
+        0x21u8 => Rgb([0x2fu8, 0x17u8, 0x7bu8]),
+        0x22u8 => Rgb([0x30u8, 0x18u8, 0x7cu8]),
+        0x23u8 => Rgb([0x32u8, 0x19u8, 0x7du8]),
+        0x24u8 => Rgb([0x34u8, 0x19u8, 0x7eu8]),
+        0x25u8 => Rgb([0x35u8, 0x1au8, 0x7fu8]),
+        0x26u8 => Rgb([0x37u8, 0x1bu8, 0x80u8]),
+        0x27u8 => Rgb([0x39u8, 0x1cu8, 0x80u8]),
+        0x28u8 => Rgb([0x3bu8, 0x1du8, 0x81u8]),
+        0x29u8 => Rgb([0x3cu8, 0x1eu8, 0x82u8]),
+        0x2au8 => Rgb([0x3eu8, 0x1fu8, 0x82u8]),
+        0x2bu8 => Rgb([0x40u8, 0x1fu8, 0x83u8]),
+        0x2cu8 => Rgb([0x42u8, 0x20u8, 0x83u8]),
+        0x2du8 => Rgb([0x43u8, 0x21u8, 0x84u8]),
+        0x2eu8 => Rgb([0x45u8, 0x22u8, 0x84u8]),
+        0x2fu8 => Rgb([0x47u8, 0x23u8, 0x85u8]),
+
+        0x30u8 => Rgb([0x49u8, 0x24u8, 0x85u8]),
+        0x31u8 => Rgb([0x4bu8, 0x25u8, 0x86u8]),
+        0x32u8 => Rgb([0x4du8, 0x26u8, 0x86u8]),
+        0x33u8 => Rgb([0x4eu8, 0x27u8, 0x86u8]),
+        0x34u8 => Rgb([0x50u8, 0x28u8, 0x87u8]),
+        0x35u8 => Rgb([0x52u8, 0x29u8, 0x87u8]),
+        0x36u8 => Rgb([0x54u8, 0x29u8, 0x87u8]),
+        0x37u8 => Rgb([0x56u8, 0x2au8, 0x87u8]),
+        0x38u8 => Rgb([0x58u8, 0x2bu8, 0x88u8]),
+        0x39u8 => Rgb([0x59u8, 0x2cu8, 0x88u8]),
+        0x3au8 => Rgb([0x5bu8, 0x2du8, 0x88u8]),
+        0x3bu8 => Rgb([0x5du8, 0x2eu8, 0x89u8]),
+        0x3cu8 => Rgb([0x5fu8, 0x2fu8, 0x89u8]),
+        0x3du8 => Rgb([0x61u8, 0x30u8, 0x89u8]),
+        0x3eu8 => Rgb([0x63u8, 0x31u8, 0x89u8]),
+        0x3fu8 => Rgb([0x65u8, 0x32u8, 0x89u8]),
+
+        0x40u8 => Rgb([0x66u8, 0x33u8, 0x89u8]),
+        0x41u8 => Rgb([0x68u8, 0x34u8, 0x8au8]),
+        0x42u8 => Rgb([0x6au8, 0x35u8, 0x8au8]),
+        0x43u8 => Rgb([0x6cu8, 0x36u8, 0x8au8]),
+        0x44u8 => Rgb([0x6eu8, 0x36u8, 0x8au8]),
+        0x45u8 => Rgb([0x70u8, 0x37u8, 0x8au8]),
+        0x46u8 => Rgb([0x72u8, 0x38u8, 0x8au8]),
+        0x47u8 => Rgb([0x74u8, 0x39u8, 0x8bu8]),
+        0x48u8 => Rgb([0x75u8, 0x3au8, 0x8bu8]),
+        0x49u8 => Rgb([0x77u8, 0x3bu8, 0x8bu8]),
+        0x4au8 => Rgb([0x79u8, 0x3cu8, 0x8bu8]),
+        0x4bu8 => Rgb([0x7bu8, 0x3du8, 0x8bu8]),
+        0x4cu8 => Rgb([0x7du8, 0x3eu8, 0x8bu8]),
+        0x4du8 => Rgb([0x7fu8, 0x3fu8, 0x8bu8]),
+        0x4eu8 => Rgb([0x80u8, 0x40u8, 0x8cu8]),
+        0x4fu8 => Rgb([0x82u8, 0x41u8, 0x8cu8]),
+
+        0x50u8 => Rgb([0x84u8, 0x42u8, 0x8cu8]),
+        0x51u8 => Rgb([0x85u8, 0x43u8, 0x8cu8]),
+        0x52u8 => Rgb([0x86u8, 0x44u8, 0x8du8]),
+        0x53u8 => Rgb([0x88u8, 0x45u8, 0x8du8]),
+        0x54u8 => Rgb([0x89u8, 0x46u8, 0x8du8]),
+        0x55u8 => Rgb([0x8au8, 0x47u8, 0x8eu8]),
+        0x56u8 => Rgb([0x8bu8, 0x48u8, 0x8eu8]),
+        0x57u8 => Rgb([0x8cu8, 0x4au8, 0x8fu8]),
+        0x58u8 => Rgb([0x8du8, 0x4bu8, 0x8fu8]),
+        0x59u8 => Rgb([0x8eu8, 0x4cu8, 0x8fu8]),
+        0x5au8 => Rgb([0x8eu8, 0x4du8, 0x90u8]),
+        0x5bu8 => Rgb([0x8fu8, 0x4eu8, 0x90u8]),
+        0x5cu8 => Rgb([0x90u8, 0x50u8, 0x91u8]),
+        0x5du8 => Rgb([0x90u8, 0x51u8, 0x91u8]),
+        0x5eu8 => Rgb([0x91u8, 0x52u8, 0x91u8]),
+        0x5fu8 => Rgb([0x91u8, 0x54u8, 0x92u8]),
+
+        0x60u8 => Rgb([0x92u8, 0x55u8, 0x92u8]),
+        0x61u8 => Rgb([0x92u8, 0x56u8, 0x92u8]),
+        0x62u8 => Rgb([0x92u8, 0x58u8, 0x93u8]),
+        0x63u8 => Rgb([0x93u8, 0x59u8, 0x93u8]),
+        0x64u8 => Rgb([0x93u8, 0x5bu8, 0x93u8]),
+        0x65u8 => Rgb([0x93u8, 0x5cu8, 0x93u8]),
+        0x66u8 => Rgb([0x93u8, 0x5eu8, 0x93u8]),
+        0x67u8 => Rgb([0x93u8, 0x5fu8, 0x93u8]),
+        0x68u8 => Rgb([0x93u8, 0x61u8, 0x93u8]),
+        0x69u8 => Rgb([0x93u8, 0x62u8, 0x93u8]),
+        0x6au8 => Rgb([0x93u8, 0x64u8, 0x93u8]),
+        0x6bu8 => Rgb([0x93u8, 0x65u8, 0x93u8]),
+        0x6cu8 => Rgb([0x93u8, 0x67u8, 0x93u8]),
+        0x6du8 => Rgb([0x93u8, 0x68u8, 0x93u8]),
+        0x6eu8 => Rgb([0x93u8, 0x6au8, 0x93u8]),
+        0x6fu8 => Rgb([0x93u8, 0x6cu8, 0x93u8]),
+
+        0x70u8 => Rgb([0x93u8, 0x6du8, 0x93u8]),
+        0x71u8 => Rgb([0x93u8, 0x6fu8, 0x93u8]),
+        0x72u8 => Rgb([0x93u8, 0x70u8, 0x93u8]),
+        0x73u8 => Rgb([0x93u8, 0x72u8, 0x93u8]),
+        0x74u8 => Rgb([0x93u8, 0x73u8, 0x93u8]),
+        0x75u8 => Rgb([0x93u8, 0x75u8, 0x93u8]),
+        0x76u8 => Rgb([0x93u8, 0x76u8, 0x93u8]),
+        0x77u8 => Rgb([0x93u8, 0x78u8, 0x93u8]),
+        0x78u8 => Rgb([0x93u8, 0x7au8, 0x93u8]),
+        0x79u8 => Rgb([0x93u8, 0x7bu8, 0x93u8]),
+        0x7au8 => Rgb([0x93u8, 0x7du8, 0x93u8]),
+        0x7bu8 => Rgb([0x93u8, 0x7eu8, 0x93u8]),
+        0x7cu8 => Rgb([0x93u8, 0x80u8, 0x93u8]),
+        0x7du8 => Rgb([0x93u8, 0x82u8, 0x93u8]),
+        0x7eu8 => Rgb([0x93u8, 0x83u8, 0x93u8]),
+        0x7fu8 => Rgb([0x93u8, 0x85u8, 0x93u8]),
+
+        0x80u8 => Rgb([0x93u8, 0x86u8, 0x93u8]),
+        0x81u8 => Rgb([0x93u8, 0x88u8, 0x93u8]),
+        0x82u8 => Rgb([0x93u8, 0x8au8, 0x93u8]),
+        0x83u8 => Rgb([0x93u8, 0x8bu8, 0x93u8]),
+        0x84u8 => Rgb([0x93u8, 0x8du8, 0x93u8]),
+        0x85u8 => Rgb([0x93u8, 0x8eu8, 0x93u8]),
+        0x86u8 => Rgb([0x93u8, 0x90u8, 0x93u8]),
+        0x87u8 => Rgb([0x93u8, 0x92u8, 0x93u8]),
+        0x88u8 => Rgb([0x93u8, 0x93u8, 0x93u8]),
+        0x89u8 => Rgb([0x93u8, 0x95u8, 0x93u8]),
+        0x8au8 => Rgb([0x93u8, 0x96u8, 0x93u8]),
+        0x8bu8 => Rgb([0x93u8, 0x98u8, 0x93u8]),
+        0x8cu8 => Rgb([0x93u8, 0x9au8, 0x93u8]),
+        0x8du8 => Rgb([0x93u8, 0x9bu8, 0x93u8]),
+        0x8eu8 => Rgb([0x93u8, 0x9du8, 0x93u8]),
+        0x8fu8 => Rgb([0x93u8, 0x9eu8, 0x93u8]),
+
+        0x90u8 => Rgb([0x93u8, 0xa0u8, 0x93u8]),
+        0x91u8 => Rgb([0x93u8, 0xa2u8, 0x93u8]),
+        0x92u8 => Rgb([0x93u8, 0xa3u8, 0x93u8]),
+        0x93u8 => Rgb([0x93u8, 0xa5u8, 0x93u8]),
+        0x94u8 => Rgb([0x93u8, 0xa7u8, 0x93u8]),
+        0x95u8 => Rgb([0x93u8, 0xa8u8, 0x93u8]),
+        0x96u8 => Rgb([0x93u8, 0xaau8, 0x93u8]),
+        0x97u8 => Rgb([0x93u8, 0xabu8, 0x93u8]),
+        0x98u8 => Rgb([0x93u8, 0xadu8, 0x93u8]),
+        0x99u8 => Rgb([0x93u8, 0xafu8, 0x93u8]),
+        0x9au8 => Rgb([0x93u8, 0xb0u8, 0x93u8]),
+        0x9bu8 => Rgb([0x93u8, 0xb2u8, 0x93u8]),
+        0x9cu8 => Rgb([0x93u8, 0xb3u8, 0x93u8]),
+        0x9du8 => Rgb([0x93u8, 0xb5u8, 0x93u8]),
+        0x9eu8 => Rgb([0x93u8, 0xb6u8, 0x93u8]),
+        0x9fu8 => Rgb([0x94u8, 0xb7u8, 0x94u8]),
+
+        0xa0u8 => Rgb([0x94u8, 0xb9u8, 0x94u8]),
+        0xa1u8 => Rgb([0x94u8, 0xbau8, 0x94u8]),
+        0xa2u8 => Rgb([0x95u8, 0xbbu8, 0x94u8]),
+        0xa3u8 => Rgb([0x95u8, 0xbdu8, 0x95u8]),
+        0xa4u8 => Rgb([0x95u8, 0xbeu8, 0x95u8]),
+        0xa5u8 => Rgb([0x96u8, 0xbfu8, 0x96u8]),
+        0xa6u8 => Rgb([0x96u8, 0xc0u8, 0x96u8]),
+        0xa7u8 => Rgb([0x97u8, 0xc1u8, 0x96u8]),
+        0xa8u8 => Rgb([0x97u8, 0xc2u8, 0x97u8]),
+        0xa9u8 => Rgb([0x98u8, 0xc3u8, 0x97u8]),
+        0xaau8 => Rgb([0x99u8, 0xc4u8, 0x98u8]),
+        0xabu8 => Rgb([0x99u8, 0xc5u8, 0x98u8]),
+        0xacu8 => Rgb([0x9au8, 0xc6u8, 0x98u8]),
+        0xadu8 => Rgb([0x9bu8, 0xc7u8, 0x99u8]),
+        0xaeu8 => Rgb([0x9cu8, 0xc8u8, 0x99u8]),
+        0xafu8 => Rgb([0x9du8, 0xc9u8, 0x99u8]),
+
+        0xb0u8 => Rgb([0x9eu8, 0xcau8, 0x9au8]),
+        0xb1u8 => Rgb([0x9fu8, 0xcbu8, 0x9au8]),
+        0xb2u8 => Rgb([0xa0u8, 0xcbu8, 0x9au8]),
+        0xb3u8 => Rgb([0xa1u8, 0xccu8, 0x9au8]),
+        0xb4u8 => Rgb([0xa2u8, 0xcdu8, 0x9au8]),
+        0xb5u8 => Rgb([0xa3u8, 0xceu8, 0x9au8]),
+        0xb6u8 => Rgb([0xa5u8, 0xceu8, 0x9bu8]),
+        0xb7u8 => Rgb([0xa6u8, 0xcfu8, 0x9bu8]),
+        0xb8u8 => Rgb([0xa7u8, 0xd0u8, 0x9bu8]),
+        0xb9u8 => Rgb([0xa8u8, 0xd0u8, 0x9bu8]),
+        0xbau8 => Rgb([0xa9u8, 0xd1u8, 0x9bu8]),
+        0xbbu8 => Rgb([0xabu8, 0xd2u8, 0x9bu8]),
+        0xbcu8 => Rgb([0xacu8, 0xd3u8, 0x9bu8]),
+        0xbdu8 => Rgb([0xadu8, 0xd4u8, 0x9cu8]),
+        0xbeu8 => Rgb([0xaeu8, 0xd4u8, 0x9cu8]),
+        0xbfu8 => Rgb([0xb0u8, 0xd5u8, 0x9cu8]),
+
+        0xc0u8 => Rgb([0xb1u8, 0xd6u8, 0x9cu8]),
+        0xc1u8 => Rgb([0xb2u8, 0xd7u8, 0x9cu8]),
+        0xc2u8 => Rgb([0xb4u8, 0xd7u8, 0x9cu8]),
+        0xc3u8 => Rgb([0xb5u8, 0xd8u8, 0x9du8]),
+        0xc4u8 => Rgb([0xb6u8, 0xd9u8, 0x9du8]),
+        0xc5u8 => Rgb([0xb8u8, 0xdau8, 0x9du8]),
+        0xc6u8 => Rgb([0xb9u8, 0xdau8, 0x9du8]),
+        0xc7u8 => Rgb([0xbau8, 0xdbu8, 0x9du8]),
+        0xc8u8 => Rgb([0xbcu8, 0xdcu8, 0x9eu8]),
+        0xc9u8 => Rgb([0xbdu8, 0xddu8, 0x9eu8]),
+        0xcau8 => Rgb([0xbeu8, 0xddu8, 0x9eu8]),
+        0xcbu8 => Rgb([0xc0u8, 0xdeu8, 0x9eu8]),
+        0xccu8 => Rgb([0xc1u8, 0xdfu8, 0x9fu8]),
+        0xcdu8 => Rgb([0xc3u8, 0xe0u8, 0x9fu8]),
+        0xceu8 => Rgb([0xc4u8, 0xe0u8, 0x9fu8]),
+        0xcfu8 => Rgb([0xc5u8, 0xe1u8, 0xa0u8]),
+
+        0xd0u8 => Rgb([0xc7u8, 0xe2u8, 0xa0u8]),
+        0xd1u8 => Rgb([0xc8u8, 0xe3u8, 0xa0u8]),
+        0xd2u8 => Rgb([0xcau8, 0xe3u8, 0xa1u8]),
+        0xd3u8 => Rgb([0xcbu8, 0xe4u8, 0xa1u8]),
+        0xd4u8 => Rgb([0xccu8, 0xe5u8, 0xa2u8]),
+        0xd5u8 => Rgb([0xceu8, 0xe6u8, 0xa2u8]),
+        0xd6u8 => Rgb([0xcfu8, 0xe6u8, 0xa3u8]),
+        0xd7u8 => Rgb([0xd1u8, 0xe7u8, 0xa3u8]),
+        0xd8u8 => Rgb([0xd2u8, 0xe8u8, 0xa4u8]),
+        0xd9u8 => Rgb([0xd3u8, 0xe9u8, 0xa4u8]),
+        0xdau8 => Rgb([0xd5u8, 0xe9u8, 0xa5u8]),
+        0xdbu8 => Rgb([0xd6u8, 0xeau8, 0xa6u8]),
+        0xdcu8 => Rgb([0xd8u8, 0xebu8, 0xa7u8]),
+        0xddu8 => Rgb([0xd9u8, 0xecu8, 0xa8u8]),
+        0xdeu8 => Rgb([0xdau8, 0xecu8, 0xa9u8]),
+        0xdfu8 => Rgb([0xdcu8, 0xedu8, 0xaau8]),
+
+        0xe0u8 => Rgb([0xddu8, 0xeeu8, 0xabu8]),
+        0xe1u8 => Rgb([0xdeu8, 0xeeu8, 0xacu8]),
+        0xe2u8 => Rgb([0xdfu8, 0xefu8, 0xadu8]),
+        0xe3u8 => Rgb([0xe1u8, 0xf0u8, 0xafu8]),
+        0xe4u8 => Rgb([0xe2u8, 0xf0u8, 0xb1u8]),
+        0xe5u8 => Rgb([0xe3u8, 0xf1u8, 0xb3u8]),
+        0xe6u8 => Rgb([0xe4u8, 0xf1u8, 0xb5u8]),
+        0xe7u8 => Rgb([0xe5u8, 0xf2u8, 0xb8u8]),
+        0xe8u8 => Rgb([0xe6u8, 0xf2u8, 0xbau8]),
+        0xe9u8 => Rgb([0xe7u8, 0xf3u8, 0xbdu8]),
+        0xeau8 => Rgb([0xe8u8, 0xf3u8, 0xbfu8]),
+        0xebu8 => Rgb([0xe9u8, 0xf4u8, 0xc2u8]),
+        0xecu8 => Rgb([0xeau8, 0xf5u8, 0xc5u8]),
+        0xedu8 => Rgb([0xebu8, 0xf5u8, 0xc7u8]),
+        0xeeu8 => Rgb([0xecu8, 0xf6u8, 0xcau8]),
+        0xefu8 => Rgb([0xedu8, 0xf6u8, 0xcdu8]),
+
+        0xf0u8 => Rgb([0xefu8, 0xf7u8, 0xd0u8]),
+        0xf1u8 => Rgb([0xf0u8, 0xf7u8, 0xd3u8]),
+        0xf2u8 => Rgb([0xf1u8, 0xf8u8, 0xd6u8]),
+        0xf3u8 => Rgb([0xf2u8, 0xf8u8, 0xd9u8]),
+        0xf4u8 => Rgb([0xf3u8, 0xf9u8, 0xdcu8]),
+        0xf5u8 => Rgb([0xf4u8, 0xf9u8, 0xdfu8]),
+        0xf6u8 => Rgb([0xf5u8, 0xfau8, 0xe2u8]),
+        0xf7u8 => Rgb([0xf6u8, 0xfbu8, 0xe5u8]),
+        0xf8u8 => Rgb([0xf7u8, 0xfbu8, 0xe8u8]),
+        0xf9u8 => Rgb([0xf8u8, 0xfcu8, 0xebu8]),
+        0xfau8 => Rgb([0xf9u8, 0xfcu8, 0xeeu8]),
+        0xfbu8 => Rgb([0xfau8, 0xfdu8, 0xf2u8]),
+        0xfcu8 => Rgb([0xfcu8, 0xfdu8, 0xf5u8]),
+        0xfdu8 => Rgb([0xfdu8, 0xfeu8, 0xf8u8]),
+        0xfeu8 => Rgb([0xfeu8, 0xfeu8, 0xfbu8]),
+        0xffu8 => Rgb([0xc9u8, 0xc9u8, 0xb9u8]),
+    }
+}
+
+/// Largest width or height of a bitmap resource
+pub const MAX_DIMENSION: u16 = 8192;
+
+/// Largest number of bits per pixel of a bitmap resource
+pub const MAX_BPP: u16 = 32;
+
+/// Length of the header at the start of a bitmap resource
+pub const HEADER_LEN: usize = 8;
+
+/// The 8 byte header at the start of a bitmap resource
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub width: u16,
+    pub height: u16,
+    /// Bits per pixel, with the top bit as a flag
+    pub depth: u16,
+    /// Bytes in each row of pixels
+    pub scanline: u16,
+}
+
+impl Header {
+    /// Read the header from the start of a resource, if there is room for one
+    pub fn parse(bytes: &[u8]) -> Option<Header> {
+        if bytes.len() < HEADER_LEN {
+            return None;
+        }
+        let field = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        Some(Header {
+            width: field(0),
+            height: field(2),
+            depth: field(4),
+            scanline: field(6),
+        })
+    }
+
+    /// The header as it is stored at the start of a resource
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0u8; HEADER_LEN];
+        bytes[0..2].copy_from_slice(&self.width.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.height.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.depth.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.scanline.to_le_bytes());
+        bytes
+    }
+
+    /// Bits per pixel, without the flag
+    pub fn bpp(&self) -> u16 {
+        self.depth & 0x7fff
+    }
+
+    /// Whether the flag bit is set, which may mean the image has transparency
+    pub fn is_transparent(&self) -> bool {
+        self.depth & 0x8000 != 0
+    }
+
+    /// The fewest bytes a row of pixels can fit in
+    pub fn min_scanline(&self) -> usize {
+        (self.width as usize * self.bpp() as usize).div_ceil(8)
+    }
+
+    /// How long the whole resource should be, header included
+    pub fn data_len(&self) -> usize {
+        HEADER_LEN + self.scanline as usize * self.height as usize
+    }
+
+    /// Bytes in each row of a 1bpp transparency mask
+    pub fn mask_len(&self) -> usize {
+        (self.width as usize).div_ceil(8)
+    }
+
+    /// Whether the rows are exactly long enough for the pixels and a mask,
+    /// with the pixels' bytes padded to a multiple of 4 as stock themes do
+    /// or not padded at all
+    ///
+    /// Any other padding is just padding, even if a mask would fit in it.
+    pub fn has_row_mask(&self) -> bool {
+        let pixels = self.min_scanline();
+        let scanline = self.scanline as usize;
+        self.is_transparent()
+            && (scanline == pixels + self.mask_len() || scanline == pixels.next_multiple_of(4) + self.mask_len())
+    }
+
+    /// Whether a resource of this size fits the header, either exactly or,
+    /// for a flagged bitmap, with a mask plane after the pixels
+    pub fn fits(&self, size: usize) -> bool {
+        size == self.data_len()
+            || self.is_transparent() && size == self.data_len() + self.mask_len() * self.height as usize
+    }
+
+    /// Check the header is within the limits shared by all the tools, and
+    /// that its rows are long enough for its pixels
+    pub fn validate(&self) -> Result<(), ThemeError> {
+        if !(1..=MAX_DIMENSION).contains(&self.width) || !(1..=MAX_DIMENSION).contains(&self.height) {
+            return Err(ThemeError::Format(format!(
+                "File may not be a bitmap resource as w is {} and h is {}",
+                self.width, self.height
+            )));
+        }
+        if !(1..=MAX_BPP).contains(&self.bpp()) {
+            return Err(ThemeError::Format(format!(
+                "File may not be a bitmap resource as it is {}bpp",
+                self.bpp()
+            )));
+        }
+        if (self.scanline as usize) < self.min_scanline() {
+            return Err(ThemeError::Format(format!(
+                "File may not be a bitmap resource as rows of {} bytes cannot hold {} pixels",
+                self.scanline, self.width
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The order of the four channels of a 32bpp bitmap
+///
+/// Written as four letters, such as `bgra`, with `x` for a byte that is not
+/// used; a bitmap without an alpha channel is opaque. Of 16bpp bitmaps, only
+/// whether blue comes before red matters, for BGR565 rather than RGB565.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChannelOrder {
+    /// Where red, green, blue and alpha are in each pixel
+    positions: [Option<usize>; 4],
+}
+
+impl ChannelOrder {
+    fn rgba(&self, pixel: &[u8]) -> Rgba<u8> {
+        let channel = |c: usize, missing: u8| self.positions[c].map_or(missing, |i| pixel[i]);
+        Rgba([channel(0, 0), channel(1, 0), channel(2, 0), channel(3, 0xff)])
+    }
+
+    /// Whether blue comes before red
+    fn is_bgr(&self) -> bool {
+        self.positions[2] < self.positions[0]
+    }
+}
+
+impl Default for ChannelOrder {
+    fn default() -> Self {
+        ChannelOrder {
+            positions: [Some(0), Some(1), Some(2), Some(3)],
+        }
+    }
+}
+
+impl FromStr for ChannelOrder {
+    type Err = ThemeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || {
+            ThemeError::Format(format!(
+                "Bad channel order {:?}, expected r, g, b and a or x, such as bgra",
+                s
+            ))
+        };
+        if s.len() != 4 {
+            return Err(bad());
+        }
+        let mut positions = [None; 4];
+        for (i, c) in s.to_ascii_lowercase().chars().enumerate() {
+            let channel = match c {
+                'r' => 0,
+                'g' => 1,
+                'b' => 2,
+                'a' => 3,
+                'x' => continue,
+                _ => return Err(bad()),
+            };
+            if positions[channel].replace(i).is_some() {
+                return Err(bad());
+            }
+        }
+        if positions[..3].contains(&None) {
+            return Err(bad());
+        }
+        Ok(ChannelOrder { positions })
+    }
+}
+
+/// How to turn the pixels of a bitmap into colours
+#[derive(Clone, Debug, Default)]
+pub struct Decoding {
+    /// Channel order of 32bpp bitmaps
+    pub order: ChannelOrder,
+    /// Colours of 8bpp bitmaps
+    pub palette: Palette,
+}
+
+/// Colormap index that is transparent in a flagged 8bpp bitmap with no mask
+pub const KEY_INDEX: u8 = 0xff;
+
+/// How a bitmap says which of its pixels are transparent
+///
+/// Masks have one bit per pixel, leftmost pixel in the high bit, set for
+/// opaque pixels. Which of these a bitmap uses follows from its flag bit and
+/// its size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transparency {
+    /// Every pixel is opaque
+    Opaque,
+    /// Each row ends with a mask, after the bytes its pixels need
+    RowMask,
+    /// A mask for the whole bitmap follows the rows of pixels
+    MaskPlane,
+    /// Pixels with this raw value are transparent
+    Key(u8),
+}
+
+/// A bitmap resource: its header and rows of pixels
+///
+/// Each row takes [`Header::scanline`] bytes, which may be more than its
+/// pixels need. The flag bit of [`Header::depth`] is kept as it is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bitmap {
+    header: Header,
+    data: Vec<u8>,
+}
+
+impl Bitmap {
+    /// A blank bitmap with rows no longer than they need to be
+    ///
+    /// If the flag bit of `depth` is set, each row ends with a mask, with
+    /// every pixel transparent.
+    pub fn new(width: u16, height: u16, depth: u16) -> Result<Bitmap, ThemeError> {
+        let mut header = Header {
+            width,
+            height,
+            depth,
+            scanline: 0,
+        };
+        let mask_len = if header.is_transparent() { header.mask_len() } else { 0 };
+        header.scanline = u16::try_from(header.min_scanline() + mask_len).map_err(|_| {
+            ThemeError::Format(format!("Rows of {} pixels are too long", width))
+        })?;
+        header.validate()?;
+        Ok(Bitmap {
+            header,
+            data: vec![0; header.data_len() - HEADER_LEN],
+        })
+    }
+
+    /// Parse a whole bitmap resource, which must be the size its header says
+    pub fn parse(bytes: &[u8]) -> Result<Bitmap, ThemeError> {
+        let header = Header::parse(bytes).ok_or_else(|| {
+            ThemeError::Format(format!("A bitmap resource cannot be {} bytes", bytes.len()))
+        })?;
+        header.validate()?;
+        if !header.fits(bytes.len()) {
+            return Err(ThemeError::Format(format!(
+                "Bitmap {} x {} with rows of {} bytes should be {} bytes, not {} (size mismatch)",
+                header.width,
+                header.height,
+                header.scanline,
+                header.data_len(),
+                bytes.len()
+            )));
+        }
+        Ok(Bitmap {
+            header,
+            data: bytes[HEADER_LEN..].to_vec(),
+        })
+    }
+
+    /// Read and parse a whole bitmap resource
+    pub fn read<R: Read>(reader: &mut R) -> Result<Bitmap, ThemeError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Bitmap::parse(&bytes)
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn width(&self) -> u32 {
+        self.header.width as u32
+    }
+
+    pub fn height(&self) -> u32 {
+        self.header.height as u32
+    }
+
+    /// The bytes of one row of pixels, including any padding
+    pub fn row(&self, y: u32) -> &[u8] {
+        let scanline = self.header.scanline as usize;
+        &self.data[y as usize * scanline..][..scanline]
+    }
+
+    /// The bytes of one row of pixels, for changing
+    pub fn row_mut(&mut self, y: u32) -> &mut [u8] {
+        let scanline = self.header.scanline as usize;
+        &mut self.data[y as usize * scanline..][..scanline]
+    }
+
+    /// The resource, header and all
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.header.data_len());
+        bytes.extend_from_slice(&self.header.to_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    /// Write the resource, header and all
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), ThemeError> {
+        writer.write_all(&self.header.to_bytes())?;
+        writer.write_all(&self.data)?;
+        Ok(())
+    }
+
+    /// How the bitmap says which pixels are transparent
+    pub fn transparency(&self) -> Transparency {
+        let header = &self.header;
+        if !header.is_transparent() {
+            Transparency::Opaque
+        } else if self.data.len() + HEADER_LEN > header.data_len() {
+            Transparency::MaskPlane
+        } else if header.has_row_mask() {
+            Transparency::RowMask
+        } else if header.bpp() == 8 {
+            Transparency::Key(KEY_INDEX)
+        } else {
+            Transparency::Opaque
+        }
+    }
+
+    /// The mask bits for one row, if the bitmap has a mask
+    fn mask_row(&self, y: u32) -> Option<&[u8]> {
+        let mask_len = self.header.mask_len();
+        match self.transparency() {
+            Transparency::RowMask => {
+                let row = self.row(y);
+                Some(&row[row.len() - mask_len..])
+            }
+            Transparency::MaskPlane => {
+                let plane = &self.data[self.header.data_len() - HEADER_LEN..];
+                Some(&plane[y as usize * mask_len..][..mask_len])
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether a pixel is opaque
+    pub fn is_opaque(&self, x: u32, y: u32) -> bool {
+        match self.transparency() {
+            Transparency::Opaque => true,
+            Transparency::Key(key) => self.row(y)[x as usize] != key,
+            _ => {
+                let mask = self.mask_row(y).unwrap();
+                mask[x as usize / 8] & (0x80 >> (x % 8)) != 0
+            }
+        }
+    }
+
+    /// Convert to an image, with the builtin palette and 32bpp pixels in RGBA order
+    pub fn to_image(&self) -> Result<DynamicImage, ThemeError> {
+        self.to_image_with(&Decoding::default())
+    }
+
+    /// Convert to an image
+    ///
+    /// 1, 2 and 4bpp bitmaps are packed grayscale with the leftmost pixel
+    /// in the high bits, 8bpp bitmaps are colormap indexes into the palette,
+    /// 16bpp are RGB565, or BGR565 if the order has blue first, 24bpp are RGB
+    /// and 32bpp have their channels in the given order. With the gray
+    /// palette, 8bpp bitmaps become grayscale images.
+    pub fn to_image_with(&self, decoding: &Decoding) -> Result<DynamicImage, ThemeError> {
+        let (w, h) = (self.width(), self.height());
+        let image = match self.header.bpp() {
+            bpp @ (1 | 2 | 4) => {
+                let max = (1u16 << bpp) - 1;
+                DynamicImage::ImageLuma8(GrayImage::from_fn(w, h, |x, y| {
+                    let value = self.packed(x, y, bpp) as u16;
+                    Luma([(value * 255 / max) as u8])
+                }))
+            }
+            8 if decoding.palette == Palette::Gray => {
+                DynamicImage::ImageLuma8(GrayImage::from_fn(w, h, |x, y| Luma([self.row(y)[x as usize]])))
+            }
+            8 => DynamicImage::ImageRgb8(RgbImage::from_fn(w, h, |x, y| {
+                decoding.palette.colour(self.row(y)[x as usize])
+            })),
+            16 => DynamicImage::ImageRgb8(RgbImage::from_fn(w, h, |x, y| {
+                let i = x as usize * 2;
+                let value = u16::from_le_bytes([self.row(y)[i], self.row(y)[i + 1]]);
+                let mut r = (value >> 11) as u8 & 0x1f;
+                let g = (value >> 5) as u8 & 0x3f;
+                let mut b = value as u8 & 0x1f;
+                if decoding.order.is_bgr() {
+                    (r, b) = (b, r);
+                }
+                Rgb([r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2])
+            })),
+            24 => DynamicImage::ImageRgb8(RgbImage::from_fn(w, h, |x, y| {
+                let i = x as usize * 3;
+                let row = self.row(y);
+                Rgb([row[i], row[i + 1], row[i + 2]])
+            })),
+            32 => DynamicImage::ImageRgba8(RgbaImage::from_fn(w, h, |x, y| {
+                let i = x as usize * 4;
+                decoding.order.rgba(&self.row(y)[i..i + 4])
+            })),
+            bpp => {
+                return Err(ThemeError::Format(format!(
+                    "Only 1, 2, 4, 8, 16, 24 and 32bpp resources are supported, this is {}bpp",
+                    bpp
+                )))
+            }
+        };
+        if self.transparency() == Transparency::Opaque {
+            return Ok(image);
+        }
+
+        // Clear the alpha of transparent pixels
+        if let DynamicImage::ImageLuma8(_) = image {
+            let mut image = image.to_luma_alpha8();
+            for (x, y, pixel) in image.enumerate_pixels_mut() {
+                if !self.is_opaque(x, y) {
+                    pixel.0[1] = 0;
+                }
+            }
+            Ok(DynamicImage::ImageLumaA8(image))
+        } else {
+            let mut image = image.to_rgba8();
+            for (x, y, pixel) in image.enumerate_pixels_mut() {
+                if !self.is_opaque(x, y) {
+                    pixel.0[3] = 0;
+                }
+            }
+            Ok(DynamicImage::ImageRgba8(image))
+        }
+    }
+
+    /// A pixel of a 1, 2 or 4bpp bitmap
+    fn packed(&self, x: u32, y: u32, bpp: u16) -> u8 {
+        let bit = x as usize * bpp as usize;
+        let shift = 8 - bpp as usize - bit % 8;
+        (self.row(y)[bit / 8] >> shift) & ((1 << bpp) - 1)
+    }
+
+    /// Make an 8bpp bitmap from colormap indexes, a row at a time
+    ///
+    /// If `opaque` says some pixels are transparent the bitmap is flagged.
+    /// When those are exactly the pixels with index [`KEY_INDEX`], rows are
+    /// left without a mask as the key says it all, otherwise each row ends
+    /// with a mask.
+    pub fn from_indices(
+        width: u16,
+        height: u16,
+        indices: &[u8],
+        opaque: Option<&[bool]>,
+    ) -> Result<Bitmap, ThemeError> {
+        let len = width as usize * height as usize;
+        if indices.len() != len || opaque.is_some_and(|opaque| opaque.len() != len) {
+            return Err(ThemeError::Format(format!(
+                "A {} x {} bitmap needs {} pixels, not {}",
+                width,
+                height,
+                len,
+                indices.len()
+            )));
+        }
+        let opaque = opaque.filter(|opaque| opaque.contains(&false));
+        let keyed = opaque.is_some_and(|opaque| {
+            indices.iter().zip(opaque).all(|(&index, &opaque)| opaque != (index == KEY_INDEX))
+        });
+        let mut bitmap = match opaque {
+            Some(_) if keyed => {
+                let header = Header {
+                    width,
+                    height,
+                    depth: 0x8000 | 8,
+                    scanline: width,
+                };
+                header.validate()?;
+                Bitmap {
+                    header,
+                    data: vec![0; header.data_len() - HEADER_LEN],
+                }
+            }
+            Some(_) => Bitmap::new(width, height, 0x8000 | 8)?,
+            None => Bitmap::new(width, height, 8)?,
+        };
+        let w = width as usize;
+        let mask_start = bitmap.header.min_scanline();
+        for y in 0..height as usize {
+            let row = bitmap.row_mut(y as u32);
+            row[..w].copy_from_slice(&indices[y * w..][..w]);
+            if let (Some(opaque), false) = (opaque, keyed) {
+                for x in (0..w).filter(|x| opaque[y * w + x]) {
+                    row[mask_start + x / 8] |= 0x80 >> (x % 8);
+                }
+            }
+        }
+        Ok(bitmap)
+    }
+
+    /// Convert an image to an 8bpp bitmap, using the palette index nearest
+    /// each pixel's colour
+    ///
+    /// Pixels at least half opaque count as opaque.
+    pub fn from_image_with_palette(img: &DynamicImage, palette: &Palette) -> Result<Bitmap, ThemeError> {
+        let (w, h) = img.dimensions();
+        if w > MAX_DIMENSION as u32 || h > MAX_DIMENSION as u32 {
+            return Err(ThemeError::Format(format!(
+                "Images can be no more than {} pixels wide or high, this is {} x {}",
+                MAX_DIMENSION, w, h
+            )));
+        }
+        let inverse = palette.inverse();
+        let rgba = img.to_rgba8();
+        let indices: Vec<u8> = rgba.pixels().map(|pixel| inverse.index(pixel.to_rgb())).collect();
+        let opaque: Vec<bool> = rgba.pixels().map(|pixel| pixel.0[3] >= 0x80).collect();
+        let opaque = img.color().has_alpha().then_some(&opaque[..]);
+        Bitmap::from_indices(w as u16, h as u16, &indices, opaque)
+    }
+
+    /// Convert an image to a 24bpp bitmap
+    ///
+    /// If the image has any transparent pixels, the bitmap is flagged and
+    /// each row ends with a mask. Pixels at least half opaque count as opaque.
+    pub fn from_image(img: &DynamicImage) -> Result<Bitmap, ThemeError> {
+        let (w, h) = img.dimensions();
+        if w > MAX_DIMENSION as u32 || h > MAX_DIMENSION as u32 {
+            return Err(ThemeError::Format(format!(
+                "Images can be no more than {} pixels wide or high, this is {} x {}",
+                MAX_DIMENSION, w, h
+            )));
+        }
+        let rgba = img.to_rgba8();
+        let transparent = img.color().has_alpha() && rgba.pixels().any(|pixel| pixel.0[3] < 0xff);
+        let depth = if transparent { 0x8000 | 24 } else { 24 };
+        let mut bitmap = Bitmap::new(w as u16, h as u16, depth)?;
+        let mask_start = bitmap.header.min_scanline();
+        for y in 0..h {
+            let row = bitmap.row_mut(y);
+            for x in 0..w {
+                let pixel = rgba.get_pixel(x, y).0;
+                let i = x as usize * 3;
+                row[i..i + 3].copy_from_slice(&pixel[..3]);
+                if transparent && pixel[3] >= 0x80 {
+                    row[mask_start + x as usize / 8] |= 0x80 >> (x % 8);
+                }
+            }
+        }
+        Ok(bitmap)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A bitmap whose pixel data, after the header, is `byte(i)` at offset `i`
+    pub(crate) fn bitmap(
+        width: u16,
+        height: u16,
+        depth: u16,
+        scanline: u16,
+        byte: impl Fn(usize) -> u8,
+    ) -> Bitmap {
+        let header = Header { width, height, depth, scanline };
+        let mut bytes = header.to_bytes().to_vec();
+        bytes.extend((0..header.data_len() - HEADER_LEN).map(byte));
+        Bitmap::parse(&bytes).unwrap()
+    }
+
+    fn gray(bitmap: &Bitmap) -> Vec<u8> {
+        bitmap.to_image().unwrap().to_luma8().into_raw()
+    }
+
+    #[test]
+    fn packed_grayscale() {
+        let one = bitmap(10, 1, 1, 2, |i| [0b1010_0000, 0b0100_0000][i]);
+        assert_eq!(gray(&one), [255, 0, 255, 0, 0, 0, 0, 0, 0, 255]);
+        let two = bitmap(5, 1, 2, 2, |i| [0b0001_1011, 0b1100_0000][i]);
+        assert_eq!(gray(&two), [0, 85, 170, 255, 255]);
+        let four = bitmap(3, 1, 4, 2, |i| [0x0f, 0x50][i]);
+        assert_eq!(gray(&four), [0, 255, 85]);
+    }
+
+    #[test]
+    fn rgb565() {
+        // Pure red, green and blue, then the low bits of each
+        let pixels = [0xf800u16, 0x07e0, 0x001f, 0x0821];
+        let bytes: Vec<u8> = pixels.iter().flat_map(|p| p.to_le_bytes()).collect();
+        let image = bitmap(4, 1, 16, 8, |i| bytes[i]).to_image().unwrap();
+        assert_eq!(
+            image.to_rgb8().into_raw(),
+            [255, 0, 0, 0, 255, 0, 0, 0, 255, 8, 4, 8]
+        );
+
+        let decoding = Decoding {
+            order: "bgra".parse().unwrap(),
+            ..Decoding::default()
+        };
+        let image = bitmap(4, 1, 16, 8, |i| bytes[i]).to_image_with(&decoding).unwrap();
+        assert_eq!(
+            image.to_rgb8().into_raw(),
+            [0, 0, 255, 0, 255, 0, 255, 0, 0, 8, 4, 8]
+        );
+    }
+
+    #[test]
+    fn channel_orders() {
+        let bytes = [1, 2, 3, 4];
+        let rgba = |order: &str| {
+            let decoding = Decoding {
+                order: order.parse().unwrap(),
+                ..Decoding::default()
+            };
+            let image = bitmap(1, 1, 32, 4, |i| bytes[i]).to_image_with(&decoding).unwrap();
+            image.to_rgba8().get_pixel(0, 0).0
+        };
+        assert_eq!(rgba("rgba"), [1, 2, 3, 4]);
+        assert_eq!(rgba("BGRA"), [3, 2, 1, 4]);
+        assert_eq!(rgba("argb"), [2, 3, 4, 1]);
+        assert_eq!(rgba("xbgr"), [4, 3, 2, 255]);
+        assert_eq!(ChannelOrder::default(), "rgba".parse().unwrap());
+        for bad in ["rgb", "rgbax", "rgbb", "rgxx", "rgbq"] {
+            assert!(bad.parse::<ChannelOrder>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn transparency_layouts() {
+        // Stock themes pad the pixels to 4 bytes before the mask
+        let stock = bitmap(39, 19, 0x8008, 45, |_| 0);
+        assert_eq!(stock.transparency(), Transparency::RowMask);
+        let keyed = bitmap(39, 19, 0x8008, 39, |_| 0);
+        assert_eq!(keyed.transparency(), Transparency::Key(KEY_INDEX));
+        let unflagged = bitmap(39, 19, 0x0008, 45, |_| 0);
+        assert_eq!(unflagged.transparency(), Transparency::Opaque);
+        assert_eq!(bitmap(39, 19, 0x8008, 44, |_| 0).transparency(), Transparency::RowMask);
+        assert_eq!(bitmap(39, 19, 0x8008, 48, |_| 0).transparency(), Transparency::Key(KEY_INDEX));
+        // Padding that a mask would fit in is still padding
+        assert_eq!(bitmap(3, 2, 0x8018, 12, |_| 0).transparency(), Transparency::Opaque);
+        assert_eq!(bitmap(3, 2, 0x8018, 9, |_| 0).transparency(), Transparency::Opaque);
+    }
+
+    #[test]
+    fn padded_24bpp_is_opaque() {
+        let image = bitmap(3, 2, 0x8018, 12, |_| 0x40).to_image().unwrap();
+        assert!(!image.color().has_alpha());
+        assert!(image.to_rgb8().pixels().all(|pixel| pixel.0 == [0x40; 3]));
+    }
+
+    #[test]
+    fn row_mask() {
+        let mut bitmap = Bitmap::new(9, 1, 0x8018).unwrap();
+        bitmap.row_mut(0)[27..].copy_from_slice(&[0b1010_0000, 0b1000_0000]);
+        let opaque: Vec<bool> = (0..9).map(|x| bitmap.is_opaque(x, 0)).collect();
+        assert_eq!(opaque, [true, false, true, false, false, false, false, false, true]);
+    }
+
+    #[test]
+    fn from_image_round_trip() {
+        let mut image = RgbaImage::new(3, 2);
+        image.put_pixel(1, 0, Rgba([10, 20, 30, 255]));
+        image.put_pixel(2, 1, Rgba([40, 50, 60, 255]));
+        let bitmap = Bitmap::from_image(&DynamicImage::ImageRgba8(image.clone())).unwrap();
+        assert_eq!(bitmap.transparency(), Transparency::RowMask);
+        assert_eq!(bitmap.to_image().unwrap().to_rgba8(), image);
+    }
+
+    #[test]
+    fn from_indices() {
+        let indices = [1, KEY_INDEX, 3, 4];
+        let keyed = Bitmap::from_indices(2, 2, &indices, Some(&[true, false, true, true])).unwrap();
+        assert_eq!(keyed.header().scanline, 2);
+        assert_eq!(keyed.transparency(), Transparency::Key(KEY_INDEX));
+
+        let masked = Bitmap::from_indices(2, 2, &indices, Some(&[false, true, true, true])).unwrap();
+        assert_eq!(masked.transparency(), Transparency::RowMask);
+        assert!(!masked.is_opaque(0, 0) && masked.is_opaque(1, 0));
+
+        let opaque = Bitmap::from_indices(2, 2, &indices, Some(&[true; 4])).unwrap();
+        assert_eq!(opaque.header().depth, 8);
+        assert_eq!(opaque.row(1)[..2], [3, 4]);
+
+        assert!(Bitmap::from_indices(2, 2, &indices[..3], None).is_err());
+        assert!(Bitmap::from_indices(2, 2, &indices, Some(&[true; 3])).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+// Copyright 2024 Chris Ridd <chrisridd@mac.com>. All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use image::ImageError;
+
+use crate::ResourceHeader;
+
+/// Exit status when `rpbres -t` finds problems in a theme
+pub const EXIT_PROBLEMS: i32 = 1;
+/// Exit status for bad command line arguments, as used by clap, and for
+/// [`ThemeError::Usage`]
+pub const EXIT_USAGE: i32 = 2;
+/// Exit status for [`ThemeError::IO`]
+pub const EXIT_IO: i32 = 3;
+/// Exit status for [`ThemeError::Format`]
+pub const EXIT_FORMAT: i32 = 4;
+/// Exit status for [`ThemeError::Zlib`]
+pub const EXIT_ZLIB: i32 = 5;
+/// Exit status for [`ThemeError::Image`]
+pub const EXIT_IMAGE: i32 = 6;
+/// Exit status for [`ThemeError::Json`], from manifests, JSON palettes and
+/// `rpbres -l --format json`
+pub const EXIT_JSON: i32 = 7;
+/// Exit status for [`ThemeError::NotFound`]
+pub const EXIT_NOT_FOUND: i32 = 8;
+
+/// Errors from reading themes and converting their resources
+#[derive(Debug)]
+pub enum ThemeError {
+    IO(io::Error),
+    Format(String),
+    Zlib(yazi::Error),
+    Image(ImageError),
+    Json(serde_json::Error),
+    /// A resource that was asked for is not in the theme
+    NotFound(String),
+    /// The arguments cannot be used together, in a way clap cannot check
+    Usage(String),
+    /// Another error, and where in which theme it happened
+    Context(Context, Box<ThemeError>),
+}
+
+/// Where an error happened
+#[derive(Clone, Debug, Default)]
+pub struct Context {
+    pub theme: Option<PathBuf>,
+    pub resource: Option<String>,
+    pub offset: Option<u32>,
+}
+
+impl ThemeError {
+    /// Record the theme file the error happened in, unless it is already known
+    pub fn with_theme<P: AsRef<Path>>(self, path: P) -> Self {
+        let (mut context, error) = self.into_context();
+        context.theme.get_or_insert_with(|| path.as_ref().to_path_buf());
+        ThemeError::Context(context, error)
+    }
+
+    /// Record the resource the error happened in, unless it is already known
+    pub fn with_resource(self, header: &ResourceHeader) -> Self {
+        let (mut context, error) = self.into_context();
+        if context.resource.is_none() {
+            context.resource = Some(header.name.clone());
+            context.offset = Some(header.offset);
+        }
+        ThemeError::Context(context, error)
+    }
+
+    /// Where the error happened, if known
+    pub fn context(&self) -> Option<&Context> {
+        match self {
+            ThemeError::Context(context, _) => Some(context),
+            _ => None,
+        }
+    }
+
+    /// The error without any context
+    pub fn root(&self) -> &ThemeError {
+        match self {
+            ThemeError::Context(_, error) => error.root(),
+            error => error,
+        }
+    }
+
+    /// The process exit status for this class of error
+    pub fn exit_code(&self) -> i32 {
+        match self.root() {
+            ThemeError::IO(_) => EXIT_IO,
+            ThemeError::Format(_) => EXIT_FORMAT,
+            ThemeError::Zlib(_) => EXIT_ZLIB,
+            ThemeError::Image(_) => EXIT_IMAGE,
+            ThemeError::Json(_) => EXIT_JSON,
+            ThemeError::NotFound(_) => EXIT_NOT_FOUND,
+            ThemeError::Usage(_) => EXIT_USAGE,
+            ThemeError::Context(..) => unreachable!(),
+        }
+    }
+
+    fn into_context(self) -> (Context, Box<ThemeError>) {
+        match self {
+            ThemeError::Context(context, error) => (context, error),
+            error => (Context::default(), Box::new(error)),
+        }
+    }
+}
+
+impl Display for ThemeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ThemeError::IO(e) => write!(f, "I/O error: {}", e),
+            ThemeError::Format(s) => write!(f, "Bad format: {}", s),
+            ThemeError::Zlib(e) => write!(f, "Decompress: {:?}", e),
+            ThemeError::Image(e) => write!(f, "Image error: {}", e),
+            ThemeError::Json(e) => write!(f, "JSON error: {}", e),
+            ThemeError::NotFound(s) => write!(f, "Not found: {}", s),
+            ThemeError::Usage(s) => write!(f, "Usage: {}", s),
+            ThemeError::Context(context, e) => write!(f, "{}{}", context, e),
+        }
+    }
+}
+
+impl Display for Context {
+    /// Writes a prefix for the error message, such as `Line.pbt: "about:4" at offset 1234: `
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(theme) = &self.theme {
+            write!(f, "{}: ", theme.display())?;
+        }
+        match &self.resource {
+            Some(name) if name.is_empty() => write!(f, "configuration")?,
+            Some(name) => write!(f, "{:?}", name)?,
+            None => {}
+        }
+        if let Some(offset) = self.offset {
+            write!(f, " at offset {}", offset)?;
+        }
+        if self.resource.is_some() {
+            write!(f, ": ")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ThemeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ThemeError::IO(e) => Some(e),
+            ThemeError::Image(e) => Some(e),
+            ThemeError::Json(e) => Some(e),
+            ThemeError::Context(_, e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for ThemeError {
+    fn from(error: io::Error) -> Self {
+        ThemeError::IO(error)
+    }
+}
+
+impl From<yazi::Error> for ThemeError {
+    fn from(error: yazi::Error) -> Self {
+        ThemeError::Zlib(error)
+    }
+}
+
+impl From<ImageError> for ThemeError {
+    fn from(error: ImageError) -> Self {
+        ThemeError::Image(error)
+    }
+}
+
+impl From<serde_json::Error> for ThemeError {
+    fn from(error: serde_json::Error) -> Self {
+        ThemeError::Json(error)
+    }
+}
@@ -0,0 +1,331 @@
+// Copyright 2024 Chris Ridd <chrisridd@mac.com>. All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Safe file names for extracting resources
+//!
+//! Resource names come from the theme file, so they cannot be trusted as
+//! paths: a name like `../../.bashrc` or `/etc/x` must not be able to write
+//! outside the directory resources are extracted into.
+//!
+//! Names are mapped to file names by a [`Scheme`]. The plain scheme keeps
+//! names as they are where it safely can, and the percent scheme encodes
+//! anything that is not allowed in file names on some platform, such as the
+//! `:` in `about:4`, so that it can be reversed exactly.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::ThemeError;
+use crate::name;
+
+/// The theme configuration has no name, so it is extracted into this file
+pub const CONFIG_FILENAME: &str = "theme.cfg";
+
+/// Turn a resource name into a single file name component
+///
+/// Path separators and control characters become `_`, and so does a leading
+/// `.`, which rules out `.`, `..` and hidden files. On Windows `:` does too,
+/// as it would start a drive prefix or an alternate data stream. Names that
+/// need none of this are returned unchanged.
+pub fn safe_filename(name: &str) -> Cow<'_, str> {
+    let unsafe_char = |(i, c): (usize, char)| {
+        c == '/'
+            || c == '\\'
+            || (c == ':' && cfg!(windows))
+            || c.is_control()
+            || (i == 0 && c == '.')
+    };
+    if !name.char_indices().any(unsafe_char) {
+        return Cow::Borrowed(name);
+    }
+    Cow::Owned(
+        name.char_indices()
+            .map(|(i, c)| if unsafe_char((i, c)) { '_' } else { c })
+            .collect(),
+    )
+}
+
+/// The path of a file in a directory, checking that the file name really is
+/// a single name and cannot lead anywhere else
+pub fn output_path(dir: &Path, filename: &str) -> Result<PathBuf, ThemeError> {
+    let mut components = Path::new(filename).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(dir.join(filename)),
+        _ => Err(ThemeError::Format(format!("{:?} is not a safe file name", filename))),
+    }
+}
+
+/// How resource names are mapped to file names
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scheme {
+    /// Names are used as they are, except that unsafe characters become `_`
+    #[default]
+    Plain,
+    /// Characters that are unsafe on any platform are percent-encoded
+    Percent,
+}
+
+impl Scheme {
+    /// The scheme to use when none is asked for
+    ///
+    /// Windows cannot have `:` in file names, so plain names will not do there.
+    pub fn platform_default() -> Scheme {
+        if cfg!(windows) {
+            Scheme::Percent
+        } else {
+            Scheme::Plain
+        }
+    }
+
+    /// The file name for a resource name
+    pub fn filename(self, name: &str) -> Cow<'_, str> {
+        match self {
+            Scheme::Plain => safe_filename(name),
+            Scheme::Percent => percent_encode(name),
+        }
+    }
+
+    /// The resource name for a file name; the inverse of [`Scheme::filename`]
+    /// for the percent scheme
+    pub fn name(self, filename: &str) -> Cow<'_, str> {
+        match self {
+            Scheme::Plain => Cow::Borrowed(filename),
+            Scheme::Percent => percent_decode(filename),
+        }
+    }
+}
+
+impl Display for Scheme {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Scheme::Plain => write!(f, "plain"),
+            Scheme::Percent => write!(f, "percent"),
+        }
+    }
+}
+
+impl FromStr for Scheme {
+    type Err = ThemeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(Scheme::Plain),
+            "percent" => Ok(Scheme::Percent),
+            _ => Err(ThemeError::Format(format!("Unknown file name scheme {:?}", s))),
+        }
+    }
+}
+
+/// Names Windows reserves for devices, whatever the extension
+const RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Percent-encode the bytes of a name that some platform would not allow
+///
+/// `%` is encoded too, as are any bytes that are not valid UTF-8, a leading
+/// `.`, a trailing `.` or space, and the first character of a name that would
+/// be a reserved device name on Windows or would clash with the config file.
+fn percent_encode(name: &str) -> Cow<'_, str> {
+    let bytes = name::encode(name);
+    let stem = name.split('.').next().unwrap_or("");
+    let reserved = name == CONFIG_FILENAME
+        || RESERVED.iter().any(|r| r.eq_ignore_ascii_case(stem));
+
+    // Characters, or single bytes where the name is not valid UTF-8
+    let mut units = Vec::new();
+    for chunk in bytes.utf8_chunks() {
+        units.extend(chunk.valid().chars().map(Ok));
+        units.extend(chunk.invalid().iter().map(|&b| Err(b)));
+    }
+
+    let mut filename = String::with_capacity(bytes.len());
+    for (i, unit) in units.iter().enumerate() {
+        let first = i == 0;
+        let last = i == units.len() - 1;
+        match *unit {
+            Ok(c) if !(c.is_control()
+                || "/\\:*?\"<>|%".contains(c)
+                || (first && (c == '.' || reserved))
+                || (last && (c == '.' || c == ' '))) =>
+            {
+                filename.push(c)
+            }
+            Ok(c) => {
+                for b in c.encode_utf8(&mut [0u8; 4]).bytes() {
+                    filename.push_str(&format!("%{:02X}", b));
+                }
+            }
+            Err(b) => filename.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    if filename == name {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(filename)
+    }
+}
+
+/// Reverse [`percent_encode`]; a `%` not followed by two hex digits is kept
+fn percent_decode(filename: &str) -> Cow<'_, str> {
+    if !filename.contains('%') {
+        return Cow::Borrowed(filename);
+    }
+    let mut bytes = Vec::with_capacity(filename.len());
+    let mut rest = filename.as_bytes();
+    while let Some((&b, after)) = rest.split_first() {
+        // from_str_radix would also take a sign, as in "%+1"
+        let hex = after
+            .get(..2)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok());
+        match hex.filter(|_| b == b'%').and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &after[2..];
+            }
+            None => {
+                bytes.push(b);
+                rest = after;
+            }
+        }
+    }
+    Cow::Owned(name::decode(&bytes))
+}
+
+/// File names for every resource in a theme, in table order
+///
+/// The first resource is the configuration. If making names safe, or a
+/// theme with repeated names, would make two resources share a file, one
+/// keeps the name and the others get a `~2`, `~3`... suffix. Names that
+/// mapped exactly are given out first, so they are never the ones renamed.
+pub fn filenames<'a, I: IntoIterator<Item = &'a str>>(names: I, scheme: Scheme) -> Vec<String> {
+    let mut used = HashSet::new();
+    let mut filenames = Vec::new();
+    for (i, name) in names.into_iter().enumerate() {
+        let filename = if i == 0 {
+            Cow::Borrowed(CONFIG_FILENAME)
+        } else if name.is_empty() {
+            Cow::Owned(String::from("_"))
+        } else {
+            scheme.filename(name)
+        };
+        let unchanged = i == 0 || scheme == Scheme::Percent || matches!(filename, Cow::Borrowed(_));
+        let done = unchanged && used.insert(filename.to_string());
+        filenames.push((filename.into_owned(), done));
+    }
+    filenames
+        .into_iter()
+        .map(|(base, done)| {
+            if done {
+                return base;
+            }
+            let mut filename = base.clone();
+            let mut n = 1;
+            while !used.insert(filename.clone()) {
+                n += 1;
+                filename = format!("{}~{}", base, n);
+            }
+            filename
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_filenames() {
+        assert_eq!(safe_filename("../../evil"), "_._.._evil");
+        assert_eq!(safe_filename("/etc/x"), "_etc_x");
+        assert_eq!(safe_filename(".."), "_.");
+        assert_eq!(safe_filename("a\\b"), "a_b");
+        assert_eq!(safe_filename("a\u{1}b"), "a_b");
+        assert!(matches!(safe_filename("notes.txt"), Cow::Borrowed(_)));
+        if cfg!(windows) {
+            assert_eq!(safe_filename("C:evil"), "C_evil");
+        } else {
+            assert_eq!(safe_filename("about:4"), "about:4");
+        }
+    }
+
+    #[test]
+    fn output_paths() {
+        let dir = Path::new("out");
+        assert_eq!(output_path(dir, "notes.txt").unwrap(), dir.join("notes.txt"));
+        for filename in ["", ".", "..", "a/b", "/etc/x"] {
+            assert!(output_path(dir, filename).is_err(), "{:?}", filename);
+        }
+    }
+
+    #[test]
+    fn percent_round_trip() {
+        let names = [
+            "about:4",
+            "100%",
+            "theme.cfg",
+            "con.txt",
+            ".hidden",
+            "trailing. ",
+            // Names as decode gives them, so backslashes are always escaped
+            "a/b\\\\c",
+            "a\\x01b",
+            "\\xff\\xfe",
+            "caf\u{e9}",
+            "%41",
+        ];
+        for name in names {
+            let filename = Scheme::Percent.filename(name);
+            assert!(
+                !filename.chars().any(|c| c.is_control() || "/\\:*?\"<>|".contains(c)),
+                "{:?}",
+                filename
+            );
+            assert_ne!(filename, CONFIG_FILENAME);
+            assert_eq!(Scheme::Percent.name(&filename), name);
+        }
+        assert_eq!(Scheme::Percent.filename("about:4"), "about%3A4");
+        assert_eq!(percent_decode("50%"), "50%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+        assert_eq!(percent_decode("%+1%-1"), "%+1%-1");
+    }
+
+    #[test]
+    fn clashes_are_renamed() {
+        let names = ["", "theme.cfg", "a/b", "a_b", "x", "x"];
+        assert_eq!(
+            filenames(names, Scheme::Plain),
+            ["theme.cfg", "theme.cfg~2", "a_b~2", "a_b", "x", "x~2"]
+        );
+    }
+}
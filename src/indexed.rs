@@ -0,0 +1,454 @@
+// Copyright 2024 Chris Ridd <chrisridd@mac.com>. All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::io::Write;
+
+use image::error::{DecodingError, EncodingError, ImageFormatHint};
+use image::{ImageError, ImageFormat, Rgb};
+use png::{BitDepth, ColorType, Transformations};
+
+use crate::bitmap::{Bitmap, Header, Transparency, MAX_DIMENSION};
+use crate::error::ThemeError;
+use crate::palette::Palette;
+
+/// Keyword of the text chunk that records how the bitmap's rows were laid out
+const LAYOUT_KEYWORD: &str = "PocketBook bitmap";
+
+/// What a PNG cannot say about a bitmap: its depth, including the
+/// transparency flag, and its rows' length and padding
+struct Layout {
+    depth: u16,
+    scanline: u16,
+    /// Bytes after the pixels of each row, all rows together
+    padding: Vec<u8>,
+}
+
+impl Layout {
+    fn of(bitmap: &Bitmap) -> Layout {
+        let header = bitmap.header();
+        let width = bitmap.width() as usize;
+        let padding = (0..bitmap.height())
+            .flat_map(|y| bitmap.row(y)[width..].to_vec())
+            .collect();
+        Layout {
+            depth: header.depth,
+            scanline: header.scanline,
+            padding,
+        }
+    }
+
+    /// As `depth=0x8008 scanline=24`, with the padding in hex only if it is
+    /// not all zero
+    fn to_text(&self) -> String {
+        let mut text = format!("depth=0x{:04x} scanline={}", self.depth, self.scanline);
+        if self.padding.iter().any(|&b| b != 0) {
+            text.push_str(" padding=");
+            for b in &self.padding {
+                text.push_str(&format!("{:02x}", b));
+            }
+        }
+        text
+    }
+
+    fn parse(text: &str, width: u32, height: u32) -> Option<Layout> {
+        let (mut depth, mut scanline, mut padding) = (None, None, None);
+        for field in text.split_whitespace() {
+            match field.split_once('=')? {
+                ("depth", value) => {
+                    depth = Some(u16::from_str_radix(value.strip_prefix("0x")?, 16).ok()?)
+                }
+                ("scanline", value) => scanline = Some(value.parse::<u16>().ok()?),
+                ("padding", value) if value.len() % 2 == 0 => {
+                    let bytes: Option<Vec<u8>> = (0..value.len())
+                        .step_by(2)
+                        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+                        .collect();
+                    padding = Some(bytes?);
+                }
+                _ => {}
+            }
+        }
+        let scanline = scanline?;
+        let padding_len = (scanline as usize).checked_sub(width as usize)? * height as usize;
+        let padding = padding.unwrap_or_else(|| vec![0; padding_len]);
+        if padding.len() != padding_len {
+            return None;
+        }
+        Some(Layout {
+            depth: depth?,
+            scanline,
+            padding,
+        })
+    }
+}
+
+/// Whether an 8bpp bitmap can be written as a PNG without losing anything
+///
+/// Bitmaps with a mask at the end of each row can, though not as an indexed
+/// PNG, as PNG transparency is per colour.
+pub fn can_write(bitmap: &Bitmap) -> bool {
+    bitmap.header().bpp() == 8
+        && matches!(
+            bitmap.transparency(),
+            Transparency::Opaque | Transparency::Key(_) | Transparency::RowMask
+        )
+}
+
+/// Write an 8bpp bitmap as a PNG, with its colours from a palette
+///
+/// Bitmaps without a mask are written as indexed PNGs, keeping their
+/// colormap indexes as they are. Bitmaps with a mask in each row are written
+/// as RGBA, each pixel the palette's colour for its index. Either way a text
+/// chunk records the depth and everything after the pixels of each row, so
+/// reading the PNG back with [`read_png`] and the same palette gives the
+/// same bitmap.
+pub fn write_png<W: Write>(writer: W, bitmap: &Bitmap, palette: &Palette) -> Result<(), ThemeError> {
+    if !can_write(bitmap) {
+        return Err(ThemeError::Format(String::from(
+            "Only 8bpp bitmaps with no mask, or a mask in each row, can be written as PNGs",
+        )));
+    }
+    let mut encoder = png::Encoder::new(writer, bitmap.width(), bitmap.height());
+    encoder.set_depth(BitDepth::Eight);
+    let masked = bitmap.transparency() == Transparency::RowMask;
+    if masked {
+        encoder.set_color(ColorType::Rgba);
+    } else {
+        let plte: Vec<u8> = (0..=255u8).flat_map(|index| palette.colour(index).0).collect();
+        encoder.set_color(ColorType::Indexed);
+        encoder.set_palette(plte);
+    }
+    if let Transparency::Key(key) = bitmap.transparency() {
+        let mut trns = vec![0xff; key as usize + 1];
+        trns[key as usize] = 0;
+        encoder.set_trns(trns);
+    }
+    encoder
+        .add_text_chunk(String::from(LAYOUT_KEYWORD), Layout::of(bitmap).to_text())
+        .map_err(encoding_error)?;
+    let mut data = Vec::with_capacity(bitmap.width() as usize * bitmap.height() as usize);
+    for y in 0..bitmap.height() {
+        let indices = &bitmap.row(y)[..bitmap.width() as usize];
+        if masked {
+            for (x, &index) in indices.iter().enumerate() {
+                let alpha = if bitmap.is_opaque(x as u32, y) { 0xff } else { 0 };
+                data.extend_from_slice(&palette.colour(index).0);
+                data.push(alpha);
+            }
+        } else {
+            data.extend_from_slice(indices);
+        }
+    }
+    let mut writer = encoder.write_header().map_err(encoding_error)?;
+    writer.write_image_data(&data).map_err(encoding_error)?;
+    writer.finish().map_err(encoding_error)?;
+    Ok(())
+}
+
+/// Read an indexed PNG straight into an 8bpp bitmap
+///
+/// Indexes whose colour in the PNG is the palette's colour for that index
+/// are kept as they are; any others, as in a PNG whose colours have been
+/// reordered or reduced, are mapped to the palette's nearest colour.
+/// Colours that the PNG makes less than half opaque are transparent. The
+/// depth and row padding that [`write_png`] records are restored if they
+/// still fit the image. Gives `None` if the data is not an indexed PNG.
+pub fn read_png(bytes: &[u8], palette: &Palette) -> Result<Option<Bitmap>, ThemeError> {
+    if !bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Ok(None);
+    }
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(Transformations::IDENTITY);
+    let mut reader = decoder.read_info().map_err(decoding_error)?;
+    let info = reader.info();
+    // Before allocating anything the size of the image
+    let (width, height) = (info.width, info.height);
+    if width > MAX_DIMENSION as u32 || height > MAX_DIMENSION as u32 {
+        return Err(ThemeError::Format(format!(
+            "Images can be no more than {} pixels wide or high, this is {} x {}",
+            MAX_DIMENSION, width, height
+        )));
+    }
+    let layout = info
+        .uncompressed_latin1_text
+        .iter()
+        .find(|chunk| chunk.keyword == LAYOUT_KEYWORD)
+        .and_then(|chunk| Layout::parse(&chunk.text, width, height));
+    if info.color_type != ColorType::Indexed {
+        // An 8bpp bitmap with a row mask, written as RGBA
+        return match layout.filter(|layout| layout.depth & 0x7fff == 8) {
+            Some(layout) => read_rgba(bytes, palette, &layout, width, height).map(Some),
+            None => Ok(None),
+        };
+    }
+    let bits = info.bit_depth as usize;
+    let alpha: Vec<u8> = info.trns.as_deref().unwrap_or_default().to_vec();
+    let plte: Vec<u8> = info.palette.as_deref().unwrap_or_default().to_vec();
+
+    let mut buf = vec![0u8; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buf).map_err(decoding_error)?;
+    let mut indices = Vec::with_capacity(width as usize * height as usize);
+    for line in buf.chunks(frame.line_size).take(height as usize) {
+        for x in 0..width as usize {
+            let bit = x * bits;
+            let shift = 8 - bits - bit % 8;
+            indices.push((line[bit / 8] >> shift) & ((1u16 << bits) - 1) as u8);
+        }
+    }
+    let opaque: Vec<bool> = indices
+        .iter()
+        .map(|&index| alpha.get(index as usize).is_none_or(|&alpha| alpha >= 0x80))
+        .collect();
+
+    let inverse = palette.inverse();
+    let mut map: Vec<u8> = (0..=255).collect();
+    for (index, colour) in plte.chunks_exact(3).enumerate() {
+        let colour = Rgb([colour[0], colour[1], colour[2]]);
+        if index >= palette.len() || palette.colour(index as u8) != colour {
+            map[index] = inverse.index(colour);
+        }
+    }
+    for index in indices.iter_mut() {
+        *index = map[*index as usize];
+    }
+
+    if let Some(bitmap) = layout.and_then(|layout| with_layout(&layout, width, height, &indices, &opaque)) {
+        return Ok(Some(bitmap));
+    }
+    Bitmap::from_indices(width as u16, height as u16, &indices, Some(&opaque)).map(Some)
+}
+
+/// Read a PNG that [`write_png`] wrote as RGBA back into an 8bpp bitmap,
+/// with the palette index nearest each pixel's colour
+fn read_rgba(bytes: &[u8], palette: &Palette, layout: &Layout, width: u32, height: u32) -> Result<Bitmap, ThemeError> {
+    let image = image::load_from_memory_with_format(bytes, ImageFormat::Png)?.to_rgba8();
+    let inverse = palette.inverse();
+    let indices: Vec<u8> = image
+        .pixels()
+        .map(|pixel| inverse.index(Rgb([pixel[0], pixel[1], pixel[2]])))
+        .collect();
+    let opaque: Vec<bool> = image.pixels().map(|pixel| pixel[3] >= 0x80).collect();
+    match with_layout(layout, width, height, &indices, &opaque) {
+        Some(bitmap) => Ok(bitmap),
+        None => Bitmap::from_indices(width as u16, height as u16, &indices, Some(&opaque)),
+    }
+}
+
+/// Lay out an 8bpp bitmap as recorded, if that still gives every pixel its
+/// index and transparency
+fn with_layout(layout: &Layout, width: u32, height: u32, indices: &[u8], opaque: &[bool]) -> Option<Bitmap> {
+    let header = Header {
+        width: width as u16,
+        height: height as u16,
+        depth: layout.depth,
+        scanline: layout.scanline,
+    };
+    if header.bpp() != 8 {
+        return None;
+    }
+    let (w, pad) = (width as usize, (layout.scanline as usize).saturating_sub(width as usize));
+    let mut bytes = header.to_bytes().to_vec();
+    for y in 0..height as usize {
+        bytes.extend_from_slice(&indices[y * w..][..w]);
+        bytes.extend_from_slice(&layout.padding[y * pad..][..pad]);
+    }
+    let mut bitmap = Bitmap::parse(&bytes).ok()?;
+    // A mask is rebuilt from the image, in case its transparency was edited,
+    // keeping any bits past the last pixel
+    if bitmap.transparency() == Transparency::RowMask {
+        let mask_start = layout.scanline as usize - header.mask_len();
+        for y in 0..height {
+            let row = bitmap.row_mut(y);
+            for x in 0..w {
+                let bit = 0x80 >> (x % 8);
+                if opaque[y as usize * w + x] {
+                    row[mask_start + x / 8] |= bit;
+                } else {
+                    row[mask_start + x / 8] &= !bit;
+                }
+            }
+        }
+    }
+    let matches = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .zip(opaque)
+        .all(|((x, y), &opaque)| bitmap.is_opaque(x, y) == opaque);
+    matches.then_some(bitmap)
+}
+
+fn encoding_error(e: png::EncodingError) -> ThemeError {
+    ThemeError::Image(ImageError::Encoding(EncodingError::new(
+        ImageFormatHint::Exact(ImageFormat::Png),
+        e,
+    )))
+}
+
+fn decoding_error(e: png::DecodingError) -> ThemeError {
+    ThemeError::Image(ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Exact(ImageFormat::Png),
+        e,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitmap::tests::bitmap;
+    use crate::bitmap::KEY_INDEX;
+
+    fn round_trip(bitmap: &Bitmap) -> Bitmap {
+        let mut png = Vec::new();
+        write_png(&mut png, bitmap, &Palette::Builtin).unwrap();
+        read_png(&png, &Palette::Builtin).unwrap().unwrap()
+    }
+
+    #[test]
+    fn bitmaps_come_back_byte_for_byte() {
+        let byte = |i: usize| (i * 37) as u8;
+        let keyed = |i: usize| [KEY_INDEX, 1, 2, KEY_INDEX, 4, 5, 6, KEY_INDEX][i];
+        for bitmap in [
+            bitmap(5, 3, 0x0008, 5, byte),
+            bitmap(5, 3, 0x0008, 8, byte),
+            bitmap(4, 2, 0x8008, 4, keyed),
+            // Flagged, but with no pixel that has the key
+            bitmap(4, 2, 0x8008, 4, byte),
+            bitmap(3, 2, 0x8008, 8, byte),
+        ] {
+            assert!(can_write(&bitmap));
+            assert_eq!(round_trip(&bitmap).to_bytes(), bitmap.to_bytes());
+        }
+    }
+
+    /// A PNG of RGB or RGBA pixels, with a layout text chunk if given one
+    fn direct_png(width: u32, color: ColorType, pixels: &[u8], layout: Option<&str>) -> Vec<u8> {
+        let height = (pixels.len() / color.samples() / width as usize) as u32;
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, width, height);
+        encoder.set_color(color);
+        encoder.set_depth(BitDepth::Eight);
+        if let Some(layout) = layout {
+            encoder.add_text_chunk(String::from(LAYOUT_KEYWORD), String::from(layout)).unwrap();
+        }
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(pixels).unwrap();
+        writer.finish().unwrap();
+        png
+    }
+
+    #[test]
+    fn masked_bitmaps_come_back_byte_for_byte() {
+        // As arrow_page_next:4, 39 pixels padded to 40 bytes then a mask
+        let stock = bitmap(39, 19, 0x8008, 45, |i| (i * 37) as u8);
+        assert_eq!(stock.transparency(), Transparency::RowMask);
+        assert!(can_write(&stock));
+        let mut png = Vec::new();
+        write_png(&mut png, &stock, &Palette::Builtin).unwrap();
+        let image = image::load_from_memory(&png).unwrap();
+        assert_eq!(image.color(), image::ColorType::Rgba8);
+        assert_eq!(round_trip(&stock).to_bytes(), stock.to_bytes());
+    }
+
+    #[test]
+    fn edited_masks_keep_their_layout() {
+        // Pixels padded to 12 bytes, then a mask with every bit set
+        let stock = bitmap(9, 1, 0x8008, 14, |i| if i < 12 { 7 } else { 0xff });
+        assert_eq!(stock.transparency(), Transparency::RowMask);
+        let [r, g, b] = Palette::Builtin.colour(7).0;
+        let mut pixels: Vec<u8> = (0..9).flat_map(|_| [r, g, b, 0xff]).collect();
+        pixels[3] = 0;
+        let png = direct_png(9, ColorType::Rgba, &pixels, Some(&Layout::of(&stock).to_text()));
+        let bitmap = read_png(&png, &Palette::Builtin).unwrap().unwrap();
+        assert_eq!(bitmap.header(), stock.header());
+        assert!(!bitmap.is_opaque(0, 0) && bitmap.is_opaque(1, 0));
+        // Mask bits past the last pixel are kept
+        assert_eq!(bitmap.row(0)[12..], [0x7f, 0xff]);
+    }
+
+    #[test]
+    fn direct_colour_is_8bpp_if_the_layout_says_so() {
+        let palette = Palette::Builtin;
+        let pixels = [palette.colour(7).0, palette.colour(200).0].concat();
+        let png = direct_png(2, ColorType::Rgb, &pixels, Some("depth=0x0008 scanline=4"));
+        let bitmap = read_png(&png, &palette).unwrap().unwrap();
+        assert_eq!(bitmap.header().depth, 8);
+        assert_eq!(bitmap.row(0), [7, 200, 0, 0]);
+
+        let png = direct_png(2, ColorType::Rgb, &pixels, Some("depth=0x0018 scanline=6"));
+        assert!(read_png(&png, &palette).unwrap().is_none());
+        let png = direct_png(2, ColorType::Rgb, &pixels, None);
+        assert!(read_png(&png, &palette).unwrap().is_none());
+    }
+
+    #[test]
+    fn mask_planes_are_not_written() {
+        let mut bytes = Bitmap::new(3, 2, 0x0008).unwrap().to_bytes();
+        // Flag it, then add a mask for the whole bitmap
+        bytes[5] = 0x80;
+        bytes.extend_from_slice(&[0xe0, 0xe0]);
+        let bitmap = Bitmap::parse(&bytes).unwrap();
+        assert_eq!(bitmap.transparency(), Transparency::MaskPlane);
+        assert!(!can_write(&bitmap));
+        assert!(write_png(Vec::new(), &bitmap, &Palette::Builtin).is_err());
+    }
+
+    #[test]
+    fn other_palettes_are_remapped() {
+        let palette = Palette::Builtin;
+        let mut plte = Vec::new();
+        plte.extend_from_slice(&palette.colour(7).0);
+        plte.extend_from_slice(&palette.colour(200).0);
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, 2, 1);
+        encoder.set_color(ColorType::Indexed);
+        encoder.set_depth(BitDepth::Eight);
+        encoder.set_palette(plte);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[1, 0]).unwrap();
+        writer.finish().unwrap();
+
+        let bitmap = read_png(&png, &palette).unwrap().unwrap();
+        assert_eq!(bitmap.row(0), [200, 7]);
+    }
+
+    #[test]
+    fn huge_images_are_refused_before_decoding() {
+        // Only the header of a 65535 x 65535 image, which would need 4 GiB
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, 65535, 65535);
+        encoder.set_color(ColorType::Indexed);
+        encoder.set_depth(BitDepth::Eight);
+        encoder.set_palette(vec![0; 3]);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_chunk(png::chunk::IDAT, &[]).unwrap();
+        drop(writer);
+        let error = read_png(&png, &Palette::Builtin).unwrap_err();
+        assert!(matches!(error, ThemeError::Format(_)), "{}", error);
+    }
+
+    #[test]
+    fn other_images_are_not_read() {
+        assert!(read_png(b"not a png", &Palette::Builtin).unwrap().is_none());
+    }
+}
@@ -0,0 +1,487 @@
+// Copyright 2024 Chris Ridd <chrisridd@mac.com>. All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::fmt::{Display, Formatter};
+
+use crate::bitmap;
+use crate::theme::ResourceHeader;
+
+/// How many bytes from the start of a resource [`ResourceKind::kind_of`] needs
+pub const KIND_PREFIX_LEN: usize = 256;
+
+/// Every value of [`ResourceKind::name`]
+pub const KIND_NAMES: [&str; 20] = [
+    "config",
+    "bitmap",
+    "bitmap-mismatch",
+    "truetype",
+    "opentype",
+    "json",
+    "png",
+    "jpeg",
+    "gif",
+    "bmp",
+    "gzip",
+    "zlib",
+    "woff",
+    "woff2",
+    "ttc",
+    "svg",
+    "xml",
+    "ini",
+    "text",
+    "unknown",
+];
+
+/// A format recognised by the start of its data
+///
+/// [`ResourceKind::kind_with`] takes a table of these, so callers can
+/// recognise formats of their own as well as those in [`SIGNATURES`].
+#[derive(Clone, Copy, Debug)]
+pub struct Signature {
+    /// Short lower case name, as [`ResourceKind::name`]
+    pub name: &'static str,
+    /// What `rpbres -l` shows for the format
+    pub description: &'static str,
+    /// Whether the start of a resource looks like this format
+    pub test: fn(&[u8]) -> bool,
+}
+
+/// The formats [`ResourceKind::kind_of`] recognises besides bitmaps, fonts
+/// and JSON, tried in order, so that the loosest tests come last
+pub const SIGNATURES: [Signature; 13] = [
+    Signature {
+        name: "png",
+        description: "PNG image",
+        test: |bytes| bytes.starts_with(b"\x89PNG\r\n\x1a\n"),
+    },
+    Signature {
+        name: "jpeg",
+        description: "JPEG image",
+        test: |bytes| bytes.starts_with(&[0xff, 0xd8, 0xff]),
+    },
+    Signature {
+        name: "gif",
+        description: "GIF image",
+        test: |bytes| bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a"),
+    },
+    Signature {
+        name: "bmp",
+        description: "BMP image",
+        // The reserved fields after the file size are always zero
+        test: |bytes| bytes.starts_with(b"BM") && bytes.get(6..10) == Some(&[0, 0, 0, 0]),
+    },
+    Signature {
+        name: "gzip",
+        description: "gzip data",
+        test: |bytes| bytes.starts_with(&[0x1f, 0x8b, 0x08]),
+    },
+    Signature {
+        name: "zlib",
+        description: "zlib data",
+        test: |bytes| match bytes {
+            // Deflate with a window of at most 32K, no preset dictionary and a valid check
+            [cmf, flg, ..] => {
+                cmf & 0x0f == 8
+                    && cmf >> 4 <= 7
+                    && flg & 0x20 == 0
+                    && u16::from_be_bytes([*cmf, *flg]).is_multiple_of(31)
+            }
+            _ => false,
+        },
+    },
+    Signature {
+        name: "woff",
+        description: "WOFF font",
+        test: |bytes| bytes.starts_with(b"wOFF"),
+    },
+    Signature {
+        name: "woff2",
+        description: "WOFF2 font",
+        test: |bytes| bytes.starts_with(b"wOF2"),
+    },
+    Signature {
+        name: "ttc",
+        description: "TrueType collection",
+        test: |bytes| bytes.starts_with(b"ttcf"),
+    },
+    Signature {
+        name: "svg",
+        description: "SVG image",
+        test: |bytes| {
+            text(bytes).is_some_and(|text| {
+                let text = text.trim_start();
+                (text.starts_with("<?xml") || text.starts_with("<!DOCTYPE svg") || text.starts_with("<svg"))
+                    && text.contains("<svg")
+            })
+        },
+    },
+    Signature {
+        name: "xml",
+        description: "XML",
+        test: |bytes| text(bytes).is_some_and(|text| text.trim_start().starts_with("<?xml")),
+    },
+    Signature {
+        name: "ini",
+        description: "INI-like text",
+        test: |bytes| text(bytes).is_some_and(looks_like_ini),
+    },
+    Signature {
+        name: "text",
+        description: "UTF-8 text",
+        test: |bytes| text(bytes).is_some_and(|text| !text.trim().is_empty()),
+    },
+];
+
+/// Best guess at what a resource contains
+///
+/// Some details can only be worked out from the whole of a resource, and are
+/// `None` when [`ResourceKind::kind_of`] is only given the start of it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ResourceKind {
+    Config(Encoding),
+    Bitmap(bitmap::Header),
+    /// A plausible bitmap header, but the resource is not the size it says
+    MismatchedBitmap(bitmap::Header),
+    TrueType(Font),
+    OpenType(Font),
+    Json(Option<bool>),
+    /// One of the formats in a [`Signature`] table
+    Other {
+        name: &'static str,
+        description: &'static str,
+    },
+    Unknown,
+}
+
+/// Text encoding of the theme configuration
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Ascii,
+    Utf8,
+    /// UTF-8 with a byte order mark
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    /// Not text in any encoding we know
+    Binary,
+}
+
+/// What can be read from a font's header and name table
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Font {
+    /// Number of tables in the font
+    pub tables: u16,
+    /// The font family name
+    pub family: Option<String>,
+}
+
+impl ResourceKind {
+    /// Guess the kind of a resource from the start of its data
+    ///
+    /// `bytes` may be the whole of the data or just the first
+    /// [`KIND_PREFIX_LEN`] bytes of it.
+    pub fn kind_of(header: &ResourceHeader, bytes: &[u8]) -> ResourceKind {
+        ResourceKind::kind_with(header, bytes, &SIGNATURES)
+    }
+
+    /// Guess the kind of a resource, trying a table of signatures of
+    /// formats other than bitmaps, fonts and JSON
+    pub fn kind_with(header: &ResourceHeader, bytes: &[u8], signatures: &[Signature]) -> ResourceKind {
+        let whole = bytes.len() >= header.size as usize;
+        if header.name.is_empty() {
+            return ResourceKind::Config(Encoding::of(bytes, whole));
+        }
+        if let Some(bitmap) = bitmap::Header::parse(bytes) {
+            if bitmap.validate().is_ok() {
+                return if bitmap.fits(header.size as usize) {
+                    ResourceKind::Bitmap(bitmap)
+                } else {
+                    ResourceKind::MismatchedBitmap(bitmap)
+                };
+            }
+        }
+        if text(bytes).is_some_and(looks_like_json) {
+            let valid = whole.then(|| serde_json::from_slice::<serde_json::Value>(bytes).is_ok());
+            return ResourceKind::Json(valid);
+        }
+        if bytes.len() > 4 && bytes.starts_with("OTTO".as_bytes()) {
+            return ResourceKind::OpenType(Font::of(bytes));
+        }
+        if bytes.len() > 4 && bytes.starts_with(&[0x00, 0x01, 0x00, 0x00]) {
+            return ResourceKind::TrueType(Font::of(bytes));
+        }
+        match signatures.iter().find(|signature| (signature.test)(bytes)) {
+            Some(signature) => ResourceKind::Other {
+                name: signature.name,
+                description: signature.description,
+            },
+            None => ResourceKind::Unknown,
+        }
+    }
+
+    /// Whether reading the whole resource would tell us more than the start of it
+    ///
+    /// This is so for JSON, which can only be checked by parsing all of it,
+    /// and for fonts whose family name was not found, as the `name` table
+    /// holding it is rarely near the start.
+    pub fn is_guess(&self) -> bool {
+        matches!(
+            self,
+            ResourceKind::Json(None)
+                | ResourceKind::TrueType(Font { family: None, .. })
+                | ResourceKind::OpenType(Font { family: None, .. })
+        )
+    }
+
+    /// Short lower case name of the kind, for selecting resources by kind
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResourceKind::Config(_) => "config",
+            ResourceKind::Bitmap(_) => "bitmap",
+            ResourceKind::MismatchedBitmap(_) => "bitmap-mismatch",
+            ResourceKind::TrueType(_) => "truetype",
+            ResourceKind::OpenType(_) => "opentype",
+            ResourceKind::Json(_) => "json",
+            ResourceKind::Other { name, .. } => name,
+            ResourceKind::Unknown => "unknown",
+        }
+    }
+}
+
+impl Display for ResourceKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResourceKind::Config(Encoding::Ascii) => write!(f, "Configuration"),
+            ResourceKind::Config(encoding) => write!(f, "Configuration ({})", encoding),
+            ResourceKind::Bitmap(bitmap) => {
+                write!(f, "Bitmap {} x {} {}bpp", bitmap.width, bitmap.height, bitmap.bpp())?;
+                if bitmap.is_transparent() {
+                    write!(f, " *")?;
+                }
+                Ok(())
+            }
+            ResourceKind::MismatchedBitmap(_) => write!(f, "Bitmap (size mismatch)"),
+            ResourceKind::TrueType(font) => write!(f, "TrueType font{}", font),
+            ResourceKind::OpenType(font) => write!(f, "OpenType font{}", font),
+            ResourceKind::Json(None) => write!(f, "JSON?"),
+            ResourceKind::Json(Some(true)) => write!(f, "JSON"),
+            ResourceKind::Json(Some(false)) => write!(f, "JSON (invalid)"),
+            ResourceKind::Other { description, .. } => write!(f, "{}", description),
+            ResourceKind::Unknown => write!(f, "Unknown"),
+        }
+    }
+}
+
+impl Encoding {
+    /// Work out the encoding of some text, which may have been cut short
+    fn of(bytes: &[u8], whole: bool) -> Encoding {
+        if bytes.starts_with(&[0xef, 0xbb, 0xbf]) {
+            return Encoding::Utf8Bom;
+        }
+        if bytes.starts_with(&[0xff, 0xfe]) {
+            return Encoding::Utf16Le;
+        }
+        if bytes.starts_with(&[0xfe, 0xff]) {
+            return Encoding::Utf16Be;
+        }
+        if bytes.contains(&0) {
+            return Encoding::Binary;
+        }
+        if bytes.is_ascii() {
+            return Encoding::Ascii;
+        }
+        match std::str::from_utf8(bytes) {
+            Ok(_) => Encoding::Utf8,
+            // A prefix may end part way through a character
+            Err(e) if !whole && e.error_len().is_none() => Encoding::Utf8,
+            Err(_) => Encoding::Binary,
+        }
+    }
+}
+
+impl Display for Encoding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Encoding::Ascii => "ASCII",
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf8Bom => "UTF-8 with BOM",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Binary => "binary",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Font {
+    /// Read what we can of a TrueType or OpenType font
+    fn of(bytes: &[u8]) -> Font {
+        Font {
+            tables: be16(bytes, 4).unwrap_or(0),
+            family: font_family(bytes),
+        }
+    }
+}
+
+impl Display for Font {
+    /// Writes the family name after the kind, or `?` if it is not known
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.family {
+            Some(family) => write!(f, " {}", family),
+            None => write!(f, "?"),
+        }
+    }
+}
+
+/// The start of a resource as text, if it is UTF-8 without control
+/// characters, ignoring a byte order mark and a character cut short at the end
+fn text(bytes: &[u8]) -> Option<&str> {
+    let bytes = bytes.strip_prefix(&[0xef, 0xbb, 0xbf]).unwrap_or(bytes);
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&bytes[..e.valid_up_to()]).ok()?,
+        Err(_) => return None,
+    };
+    let control = |c: char| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c');
+    (!text.is_empty() && !text.contains(control)).then_some(text)
+}
+
+/// Whether text starts like a JSON object or array, rather than an INI section
+fn looks_like_json(text: &str) -> bool {
+    let text = text.trim_start();
+    match text.strip_prefix('[') {
+        Some(rest) => matches!(
+            rest.trim_start().chars().next(),
+            None | Some('{' | '[' | '"' | ']' | '-' | '0'..='9')
+        ),
+        None => text.starts_with('{'),
+    }
+}
+
+/// Whether the first line of text that is not blank or a comment is an INI
+/// section header or a `key=value` setting
+fn looks_like_ini(text: &str) -> bool {
+    let line = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with(';') && !line.starts_with('#'));
+    match line {
+        Some(line) if line.starts_with('[') => line.ends_with(']'),
+        Some(line) => line.split_once('=').is_some_and(|(key, _)| {
+            let key = key.trim();
+            !key.is_empty() && key.chars().all(|c| c.is_alphanumeric() || "_-. ".contains(c))
+        }),
+        None => false,
+    }
+}
+
+fn be16(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn be32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Find the family name in a font's `name` table
+///
+/// Windows names are preferred, in US English if there is a choice, then
+/// Unicode names and last of all Macintosh names.
+fn font_family(bytes: &[u8]) -> Option<String> {
+    let tables = be16(bytes, 4)? as usize;
+    let name = (0..tables)
+        .map(|i| 12 + i * 16)
+        .find(|&record| bytes.get(record..record + 4) == Some(b"name"))?;
+    let table = be32(bytes, name + 8)? as usize;
+
+    let count = be16(bytes, table + 2)? as usize;
+    let strings = table + be16(bytes, table + 4)? as usize;
+    let mut best: Option<(u8, String)> = None;
+    for i in 0..count {
+        let record = table + 6 + i * 12;
+        let platform = be16(bytes, record)?;
+        let language = be16(bytes, record + 4)?;
+        if be16(bytes, record + 6)? != 1 {
+            continue;
+        }
+        let len = be16(bytes, record + 8)? as usize;
+        let start = strings + be16(bytes, record + 10)? as usize;
+        let Some(raw) = bytes.get(start..start + len) else {
+            continue;
+        };
+        let (rank, family) = match platform {
+            3 | 0 => {
+                let units: Vec<u16> = raw
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                    .collect();
+                let rank = match (platform, language) {
+                    (3, 0x409) => 0,
+                    (3, _) => 1,
+                    _ => 2,
+                };
+                (rank, String::from_utf16_lossy(&units))
+            }
+            1 => (3, String::from_utf8_lossy(raw).into_owned()),
+            _ => continue,
+        };
+        if best.as_ref().is_none_or(|(best, _)| rank < *best) {
+            best = Some((rank, family));
+        }
+    }
+    best.map(|(_, family)| family).filter(|family| !family.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::theme::tests::header;
+
+    /// The kind of a resource of `size` bytes which starts with `bytes`
+    fn kind(name: &str, size: u32, bytes: &[u8]) -> ResourceKind {
+        ResourceKind::kind_of(&header(name, size), bytes)
+    }
+
+    #[test]
+    fn json() {
+        let json = kind("layout:4", 1000, b"{\"a\": 1");
+        assert_eq!(json, ResourceKind::Json(None));
+        assert_eq!(json.to_string(), "JSON?");
+        assert_eq!(kind("layout:4", 8, b"{\"a\": 1}"), ResourceKind::Json(Some(true)));
+        assert_eq!(kind("layout:4", 7, b"{\"a\": }"), ResourceKind::Json(Some(false)));
+        assert_eq!(kind("notes", 10, &[0xff; 10]), ResourceKind::Unknown);
+    }
+
+    #[test]
+    fn guesses() {
+        // A font with no name table in the prefix needs reading in full
+        let font = [&[0, 1, 0, 0, 0, 0][..], &[0; 10]].concat();
+        assert!(kind("font.ttf", 1000, &font).is_guess());
+        assert!(kind("layout:4", 1000, b"{\"a\": 1").is_guess());
+        assert!(!kind("layout:4", 8, b"{\"a\": 1}").is_guess());
+        assert!(!kind("notes", 10, b"plain text").is_guess());
+    }
+}
@@ -0,0 +1,64 @@
+// Copyright 2024 Chris Ridd <chrisridd@mac.com>. All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Tools for working with PocketBook theme files
+//!
+//! A theme (`.pbt`) is a table of named, zlib-compressed resources: the theme
+//! configuration, bitmaps, fonts and JSON layouts. [`Theme`] reads them and
+//! [`ThemeWriter`] creates them.
+//!
+//! ```no_run
+//! use pbtools::{ResourceKind, Theme};
+//!
+//! let theme = Theme::open("Line.pbt")?;
+//! for res in theme.resources() {
+//!     let data = res.data()?;
+//!     let kind = ResourceKind::kind_of(res.header(), &data);
+//!     println!("{} {}", res.name(), kind);
+//! }
+//! # Ok::<(), pbtools::ThemeError>(())
+//! ```
+
+pub mod bitmap;
+pub mod error;
+pub mod extract;
+pub mod indexed;
+pub mod kind;
+pub mod listing;
+pub mod manifest;
+pub mod name;
+pub mod palette;
+pub mod parallel;
+pub mod select;
+pub mod table;
+pub mod theme;
+pub mod verify;
+pub mod writer;
+
+pub use error::ThemeError;
+pub use kind::ResourceKind;
+pub use manifest::Manifest;
+pub use theme::{Resource, ResourceHeader, Resources, Theme};
+pub use writer::ThemeWriter;
@@ -0,0 +1,343 @@
+// Copyright 2024 Chris Ridd <chrisridd@mac.com>. All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::cmp::Ordering;
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::error::ThemeError;
+use crate::kind::ResourceKind;
+use crate::manifest;
+use crate::theme::ResourceHeader;
+
+/// Every field of an [`Entry`], in the order they are written
+pub const FIELDS: [&str; 13] = [
+    "index",
+    "name",
+    "size",
+    "compressed_size",
+    "ratio",
+    "offset",
+    "hash",
+    "kind",
+    "description",
+    "width",
+    "height",
+    "bpp",
+    "transparent",
+];
+
+/// What a listing says about one resource
+///
+/// The bitmap fields are only set for resources that look like bitmaps.
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    /// Position in the resource table
+    pub index: usize,
+    pub name: String,
+    pub size: u32,
+    pub compressed_size: u32,
+    /// Compressed size as a fraction of the size, if the size is not 0
+    pub ratio: Option<f64>,
+    pub offset: u32,
+    /// Hash of the uncompressed data, if one was asked for
+    pub hash: Option<String>,
+    /// Short name of the kind, as [`ResourceKind::name`]
+    pub kind: &'static str,
+    /// Description of the kind, as shown by `rpbres -l`
+    pub description: String,
+    pub width: Option<u16>,
+    pub height: Option<u16>,
+    pub bpp: Option<u16>,
+    pub transparent: Option<bool>,
+}
+
+impl Entry {
+    /// Describe a resource from its table entry and the start of its data
+    pub fn new(index: usize, header: &ResourceHeader, prefix: &[u8]) -> Entry {
+        let kind = ResourceKind::kind_of(header, prefix);
+        let bitmap = match kind {
+            ResourceKind::Bitmap(bitmap) | ResourceKind::MismatchedBitmap(bitmap) => Some(bitmap),
+            _ => None,
+        };
+        Entry {
+            index,
+            name: header.name.clone(),
+            size: header.size,
+            compressed_size: header.compressed_size,
+            ratio: match header.size {
+                0 => None,
+                size => Some(header.compressed_size as f64 / size as f64),
+            },
+            offset: header.offset,
+            hash: None,
+            kind: kind.name(),
+            description: kind.to_string(),
+            width: bitmap.map(|bitmap| bitmap.width),
+            height: bitmap.map(|bitmap| bitmap.height),
+            bpp: bitmap.map(|bitmap| bitmap.bpp()),
+            transparent: bitmap.map(|bitmap| bitmap.is_transparent()),
+        }
+    }
+
+    /// The value of a field as text, or `None` if there is no such field
+    ///
+    /// Bitmap fields of other kinds of resource are empty.
+    pub fn field(&self, field: &str) -> Option<String> {
+        fn optional<T: ToString>(value: Option<T>) -> String {
+            value.map(|value| value.to_string()).unwrap_or_default()
+        }
+        Some(match field {
+            "index" => self.index.to_string(),
+            "name" => self.name.clone(),
+            "size" => self.size.to_string(),
+            "compressed_size" => self.compressed_size.to_string(),
+            "ratio" => optional(self.ratio.map(|ratio| format!("{:.3}", ratio))),
+            "offset" => self.offset.to_string(),
+            "hash" => optional(self.hash.as_ref()),
+            "kind" => self.kind.to_string(),
+            "description" => self.description.clone(),
+            "width" => optional(self.width),
+            "height" => optional(self.height),
+            "bpp" => optional(self.bpp),
+            "transparent" => optional(self.transparent),
+            _ => return None,
+        })
+    }
+}
+
+/// Hashes that a listing can show for each resource
+#[derive(Clone, Copy, Debug)]
+pub enum Hash {
+    Sha256,
+    Crc32,
+}
+
+impl Hash {
+    /// Hash some data, as lower case hex
+    pub fn hex(&self, data: &[u8]) -> String {
+        match self {
+            Hash::Sha256 => manifest::sha256_hex(data),
+            Hash::Crc32 => format!("{:08x}", crc32fast::hash(data)),
+        }
+    }
+}
+
+/// Orders that a listing can be sorted in
+#[derive(Clone, Copy, Debug)]
+pub enum SortKey {
+    Size,
+    CompressedSize,
+    Ratio,
+    Name,
+    Offset,
+}
+
+impl SortKey {
+    /// Compare two entries, falling back to table order for equal keys
+    pub fn compare(&self, a: &Entry, b: &Entry) -> Ordering {
+        let ordering = match self {
+            SortKey::Size => a.size.cmp(&b.size),
+            SortKey::CompressedSize => a.compressed_size.cmp(&b.compressed_size),
+            SortKey::Ratio => a.ratio.partial_cmp(&b.ratio).unwrap_or(Ordering::Equal),
+            SortKey::Name => a.name.cmp(&b.name),
+            SortKey::Offset => a.offset.cmp(&b.offset),
+        };
+        ordering.then(a.index.cmp(&b.index))
+    }
+}
+
+/// One piece of a [`Template`]
+enum Part {
+    Text(String),
+    Field(String),
+}
+
+/// A line of text with `{field}` placeholders, written once per resource
+///
+/// `\t`, `\n` and `\\` are escapes for a tab, a newline and a backslash, and
+/// `{{` and `}}` for literal braces, so templates are easy to give in a shell.
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    /// Parse a template, checking that every field exists
+    pub fn parse(template: &str) -> Result<Template, ThemeError> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('t') => text.push('\t'),
+                    Some('n') => text.push('\n'),
+                    Some('\\') | None => text.push('\\'),
+                    Some(c) => {
+                        text.push('\\');
+                        text.push(c);
+                    }
+                },
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').ok_or_else(|| {
+                        ThemeError::Format(format!("Unclosed {{ in template {:?}", template))
+                    })?;
+                    let field = &rest[..end];
+                    if !FIELDS.contains(&field) {
+                        return Err(ThemeError::Format(format!(
+                            "Unknown field {{{}}} in template, expected one of {}",
+                            field,
+                            FIELDS.join(", ")
+                        )));
+                    }
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Field(field.to_string()));
+                    chars = rest[end + 1..].chars();
+                }
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Template { parts })
+    }
+
+    /// Fill in the template for one resource
+    pub fn render(&self, entry: &Entry) -> String {
+        let mut line = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => line.push_str(text),
+                Part::Field(field) => line.push_str(&entry.field(field).unwrap_or_default()),
+            }
+        }
+        line
+    }
+}
+
+/// Write entries as a JSON array of objects
+pub fn write_json<W: Write>(writer: &mut W, entries: &[Entry]) -> Result<(), ThemeError> {
+    serde_json::to_writer_pretty(&mut *writer, entries)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+/// Write entries as CSV, with a header line
+pub fn write_csv<W: Write>(writer: &mut W, entries: &[Entry]) -> Result<(), ThemeError> {
+    write_delimited(writer, entries, ',', csv_quote)
+}
+
+/// Write entries as tab separated values, with a header line
+pub fn write_tsv<W: Write>(writer: &mut W, entries: &[Entry]) -> Result<(), ThemeError> {
+    write_delimited(writer, entries, '\t', tsv_escape)
+}
+
+fn write_delimited<W: Write>(
+    writer: &mut W,
+    entries: &[Entry],
+    separator: char,
+    escape: fn(&str) -> String,
+) -> Result<(), ThemeError> {
+    writeln!(writer, "{}", FIELDS.join(&separator.to_string()))?;
+    for entry in entries {
+        let values: Vec<String> = FIELDS
+            .iter()
+            .map(|field| escape(&entry.field(field).unwrap_or_default()))
+            .collect();
+        writeln!(writer, "{}", values.join(&separator.to_string()))?;
+    }
+    Ok(())
+}
+
+/// Quote a CSV value if it needs it, as RFC 4180 does
+fn csv_quote(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// TSV values cannot hold tabs or line breaks, so escape them
+fn tsv_escape(value: &str) -> String {
+    value
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::theme::tests::header;
+
+    fn entry() -> Entry {
+        let header = ResourceHeader {
+            offset: 64,
+            compressed_size: 50,
+            ..header("about:4", 200)
+        };
+        Entry::new(3, &header, b"plain text")
+    }
+
+    #[test]
+    fn templates() {
+        let render = |template: &str| Template::parse(template).unwrap().render(&entry());
+        assert_eq!(render("{name}\\t{size}\\n"), "about:4\t200\n");
+        assert_eq!(render("{index}: {offset}"), "3: 64");
+        assert_eq!(render("{ratio}"), "0.250");
+        assert_eq!(render("{{{name}}} \\\\ \\q"), "{about:4} \\ \\q");
+        assert_eq!(render("{width}x{height}"), "x");
+        assert_eq!(render("no fields"), "no fields");
+    }
+
+    #[test]
+    fn bad_templates() {
+        assert!(Template::parse("{nope}").is_err());
+        assert!(Template::parse("{name").is_err());
+    }
+
+    #[test]
+    fn csv_has_a_header_and_a_line_per_entry() {
+        let mut out = Vec::new();
+        write_csv(&mut out, &[entry(), entry()]).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 3);
+        assert!(out.starts_with("index,name,"));
+    }
+}
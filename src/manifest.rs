@@ -0,0 +1,118 @@
+// Copyright 2024 Chris Ridd <chrisridd@mac.com>. All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use yazi::CompressionLevel;
+
+use crate::error::ThemeError;
+use crate::extract::Scheme;
+
+/// Name of the manifest file written by a full extraction
+pub const MANIFEST_FILENAME: &str = ".manifest.json";
+
+/// Record of a fully extracted theme
+///
+/// This keeps everything that unpacking the individual resources loses, so
+/// that packing an unchanged directory gives back the original theme.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Theme file the resources were extracted from
+    pub source: Option<String>,
+    /// How resource names were mapped to file names
+    #[serde(default)]
+    pub scheme: Scheme,
+    /// Resources in table order; the first is the configuration
+    pub resources: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub name: String,
+    /// File the resource was extracted into, relative to the manifest
+    pub file: String,
+    pub size: u32,
+    pub offset: u32,
+    pub compressed_size: u32,
+    /// FLEVEL bits from the zlib header of the compressed stream
+    pub level: u8,
+    /// SHA-256 of the extracted data
+    pub sha256: String,
+    /// SHA-256 of the compressed stream as stored in the theme
+    pub compressed_sha256: String,
+}
+
+impl Manifest {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ThemeError> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ThemeError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn entry(&self, name: &str) -> Option<&ManifestEntry> {
+        self.resources.iter().find(|entry| entry.name == name)
+    }
+
+    /// Whether each resource's data directly follows the one before it in the
+    /// table, as [`ThemeWriter`](crate::ThemeWriter) lays it out
+    pub fn is_contiguous(&self) -> bool {
+        self.resources
+            .windows(2)
+            .all(|pair| pair[1].offset as u64 == pair[0].offset as u64 + pair[0].compressed_size as u64)
+    }
+}
+
+/// Hex encoded SHA-256 of some bytes
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// The FLEVEL bits from a zlib stream header
+pub fn zlib_level(compressed: &[u8]) -> u8 {
+    compressed.get(1).map_or(2, |flg| flg >> 6)
+}
+
+/// The closest compression level to a zlib FLEVEL
+pub fn compression_level(level: u8) -> CompressionLevel {
+    match level {
+        0 | 1 => CompressionLevel::BestSpeed,
+        2 => CompressionLevel::Default,
+        _ => CompressionLevel::BestSize,
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright 2024 Chris Ridd <chrisridd@mac.com>. All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Resource names
+//!
+//! Names are stored in the theme as bytes, which are usually UTF-8. They are
+//! decoded into strings so that nothing is lost: bytes which are not valid
+//! UTF-8 are written as `\xNN` escapes and a backslash is written as `\\`.
+//! Control characters are escaped too, a `\xNN` for each of their bytes, so
+//! that names are safe to show on a terminal. [`encode`] reverses this
+//! exactly.
+
+/// Decode a name from the bytes stored in a theme
+pub fn decode(bytes: &[u8]) -> String {
+    let mut name = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if c == '\\' {
+                name.push_str("\\\\");
+            } else if c.is_control() {
+                for b in c.encode_utf8(&mut [0u8; 4]).bytes() {
+                    name.push_str(&format!("\\x{:02x}", b));
+                }
+            } else {
+                name.push(c);
+            }
+        }
+        for b in chunk.invalid() {
+            name.push_str(&format!("\\x{:02x}", b));
+        }
+    }
+    name
+}
+
+/// Encode a name into the bytes to store in a theme
+///
+/// A backslash which does not start a valid escape is kept as it is.
+pub fn encode(name: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(i) = rest.find('\\') {
+        bytes.extend_from_slice(&rest.as_bytes()[..i]);
+        rest = &rest[i..];
+        if let Some(after) = rest.strip_prefix("\\\\") {
+            bytes.push(b'\\');
+            rest = after;
+        } else if let Some(b) = rest
+            .get(2..4)
+            .filter(|hex| rest.starts_with("\\x") && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            bytes.push(b);
+            rest = &rest[4..];
+        } else {
+            bytes.push(b'\\');
+            rest = &rest[1..];
+        }
+    }
+    bytes.extend_from_slice(rest.as_bytes());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let names: [&[u8]; 7] = [
+            b"about:4",
+            "caf\u{e9}".as_bytes(),
+            b"a\\b",
+            b"\xff\xfeinvalid",
+            b"\\x41",
+            b"a\x01b\x1b[31m",
+            "c\u{85}d".as_bytes(),
+        ];
+        for bytes in names {
+            assert_eq!(encode(&decode(bytes)), bytes, "{:?}", bytes);
+        }
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(decode(b"about:4"), "about:4");
+        assert_eq!(decode(b"a\\b"), "a\\\\b");
+        assert_eq!(decode(b"a\xffb"), "a\\xffb");
+        assert_eq!(encode("a\\x41\\\\"), b"aA\\");
+        assert_eq!(decode(b"a\x01b\x7f"), "a\\x01b\\x7f");
+        assert_eq!(decode("c\u{85}d".as_bytes()), "c\\xc2\\x85d");
+        assert!(!decode(b"\x1b[31m").chars().any(char::is_control));
+    }
+
+    #[test]
+    fn lone_backslashes_are_kept() {
+        assert_eq!(encode("a\\"), b"a\\");
+        assert_eq!(encode("\\q"), b"\\q");
+        assert_eq!(encode("\\x4"), b"\\x4");
+        assert_eq!(encode("\\xzz"), b"\\xzz");
+        assert_eq!(encode("\\x+f\\x-1"), b"\\x+f\\x-1");
+    }
+}
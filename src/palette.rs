@@ -0,0 +1,265 @@
+// Copyright 2024 Chris Ridd <chrisridd@mac.com>. All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use image::Rgb;
+
+use crate::bitmap;
+use crate::error::ThemeError;
+
+/// How the 256 colormap indexes of an 8bpp bitmap become colours
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Palette {
+    /// Each index is a gray level, from black to white
+    Gray,
+    /// The colormap that `res2image` has always used
+    #[default]
+    Builtin,
+    /// Colours from a palette file, black past the end of it
+    Colours(Vec<Rgb<u8>>),
+}
+
+impl Palette {
+    /// A palette by name, `gray` or `builtin`, or else from a file
+    pub fn from_arg(arg: &str) -> Result<Palette, ThemeError> {
+        match arg {
+            "gray" | "grey" => Ok(Palette::Gray),
+            "builtin" => Ok(Palette::Builtin),
+            path => Palette::load(path),
+        }
+    }
+
+    /// Load a GIMP `.gpl`, Adobe `.act` or JSON palette file
+    ///
+    /// A JSON palette is an array of colours, each either a `"#rrggbb"`
+    /// string or an array of red, green and blue.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Palette, ThemeError> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|e| ThemeError::from(e).with_theme(path))?;
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        let colours = match extension.as_deref() {
+            Some("gpl") => parse_gpl(&bytes),
+            Some("act") => parse_act(&bytes),
+            Some("json") => parse_json(&bytes),
+            _ => Err(ThemeError::Format(String::from(
+                "Palette files must be .gpl, .act or .json",
+            ))),
+        }
+        .map_err(|e| e.with_theme(path))?;
+        if colours.is_empty() || colours.len() > 256 {
+            return Err(ThemeError::Format(format!(
+                "A palette needs 1 to 256 colours, this has {}",
+                colours.len()
+            ))
+            .with_theme(path));
+        }
+        Ok(Palette::Colours(colours))
+    }
+
+    /// The colour of a colormap index
+    pub fn colour(&self, index: u8) -> Rgb<u8> {
+        match self {
+            Palette::Gray => Rgb([index; 3]),
+            Palette::Builtin => bitmap::pixel_to_rgb(index),
+            Palette::Colours(colours) => colours.get(index as usize).copied().unwrap_or(Rgb([0; 3])),
+        }
+    }
+
+    /// How many colours the palette has
+    pub fn len(&self) -> usize {
+        match self {
+            Palette::Colours(colours) => colours.len(),
+            _ => 256,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// For looking up the index of a colour
+    pub fn inverse(&self) -> Inverse {
+        let colours: Vec<Rgb<u8>> = (0..self.len()).map(|i| self.colour(i as u8)).collect();
+        let mut exact = HashMap::new();
+        for (i, colour) in colours.iter().enumerate() {
+            exact.entry(colour.0).or_insert(i as u8);
+        }
+        Inverse { colours, exact }
+    }
+}
+
+/// Finds the palette index of a colour
+pub struct Inverse {
+    colours: Vec<Rgb<u8>>,
+    exact: HashMap<[u8; 3], u8>,
+}
+
+impl Inverse {
+    /// The index of a colour in the palette, or of the nearest colour
+    pub fn index(&self, colour: Rgb<u8>) -> u8 {
+        if let Some(&index) = self.exact.get(&colour.0) {
+            return index;
+        }
+        let distance = |other: &Rgb<u8>| -> u32 {
+            colour.0
+                .iter()
+                .zip(other.0)
+                .map(|(&a, b)| (a as i32 - b as i32).pow(2) as u32)
+                .sum()
+        };
+        (0..self.colours.len())
+            .min_by_key(|&i| distance(&self.colours[i]))
+            .unwrap_or(0) as u8
+    }
+}
+
+/// GIMP palettes are text: a `GIMP Palette` line, then settings, comments
+/// and a line per colour starting with its red, green and blue
+fn parse_gpl(bytes: &[u8]) -> Result<Vec<Rgb<u8>>, ThemeError> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut lines = text.lines();
+    if lines.next().map(str::trim) != Some("GIMP Palette") {
+        return Err(ThemeError::Format(String::from("Not a GIMP palette")));
+    }
+    let mut colours = Vec::new();
+    for line in lines.map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.contains(':') {
+            continue;
+        }
+        let channels: Vec<u8> = line
+            .split_whitespace()
+            .take(3)
+            .map(|channel| channel.parse::<u8>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| ThemeError::Format(format!("Bad colour {:?} in GIMP palette", line)))?;
+        match channels[..] {
+            [r, g, b] => colours.push(Rgb([r, g, b])),
+            _ => return Err(ThemeError::Format(format!("Bad colour {:?} in GIMP palette", line))),
+        }
+    }
+    Ok(colours)
+}
+
+/// Adobe colour tables are 256 RGB triples, optionally followed by the
+/// number of colours used and a transparent index
+fn parse_act(bytes: &[u8]) -> Result<Vec<Rgb<u8>>, ThemeError> {
+    let count = match bytes.len() {
+        768 => 256,
+        772 => (u16::from_be_bytes([bytes[768], bytes[769]]) as usize).clamp(1, 256),
+        len => {
+            return Err(ThemeError::Format(format!(
+                "An Adobe colour table is 768 or 772 bytes, not {}",
+                len
+            )))
+        }
+    };
+    Ok(bytes[..count * 3]
+        .chunks_exact(3)
+        .map(|rgb| Rgb([rgb[0], rgb[1], rgb[2]]))
+        .collect())
+}
+
+fn parse_json(bytes: &[u8]) -> Result<Vec<Rgb<u8>>, ThemeError> {
+    let value: serde_json::Value = serde_json::from_slice(bytes)?;
+    let bad = |colour: &serde_json::Value| {
+        ThemeError::Format(format!("Bad colour {} in JSON palette, expected \"#rrggbb\" or [r, g, b]", colour))
+    };
+    let Some(colours) = value.as_array() else {
+        return Err(ThemeError::Format(String::from("A JSON palette must be an array of colours")));
+    };
+    colours
+        .iter()
+        .map(|colour| {
+            if let Some(hex) = colour.as_str().and_then(|s| s.strip_prefix('#')) {
+                let rgb = u32::from_str_radix(hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 6 && hex.bytes().all(|b| b.is_ascii_hexdigit()));
+                let rgb = rgb.ok_or_else(|| bad(colour))?;
+                return Ok(Rgb([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8]));
+            }
+            let channels: Option<Vec<u8>> = colour.as_array().and_then(|channels| {
+                channels
+                    .iter()
+                    .map(|channel| channel.as_u64().and_then(|c| u8::try_from(c).ok()))
+                    .collect()
+            });
+            match channels.as_deref() {
+                Some(&[r, g, b]) => Ok(Rgb([r, g, b])),
+                _ => Err(bad(colour)),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gpl() {
+        let text = "GIMP Palette\nName: Test\nColumns: 2\n# a comment\n\n255 0 0\tRed\n  0 128 255 Sky\n";
+        assert_eq!(parse_gpl(text.as_bytes()).unwrap(), [Rgb([255, 0, 0]), Rgb([0, 128, 255])]);
+        assert!(parse_gpl(b"255 0 0\n").is_err());
+        assert!(parse_gpl(b"GIMP Palette\n255 0\n").is_err());
+        assert!(parse_gpl(b"GIMP Palette\n256 0 0\n").is_err());
+    }
+
+    #[test]
+    fn act() {
+        let mut bytes: Vec<u8> = (0..768).map(|i| i as u8).collect();
+        let colours = parse_act(&bytes).unwrap();
+        assert_eq!(colours.len(), 256);
+        assert_eq!(colours[1], Rgb([3, 4, 5]));
+        // The number of colours used, then the transparent index
+        bytes.extend([0, 2, 0xff, 0xff]);
+        assert_eq!(parse_act(&bytes).unwrap(), [Rgb([0, 1, 2]), Rgb([3, 4, 5])]);
+        bytes[769] = 0;
+        assert_eq!(parse_act(&bytes).unwrap().len(), 1);
+        assert!(parse_act(&bytes[..770]).is_err());
+    }
+
+    #[test]
+    fn json() {
+        let colours = parse_json(br##"["#ff8000", [1, 2, 3], "#00FFaa"]"##).unwrap();
+        assert_eq!(colours, [Rgb([255, 128, 0]), Rgb([1, 2, 3]), Rgb([0, 255, 170])]);
+        let bad_palettes = [
+            r##"["#ff80"]"##,
+            r##"["ff8000"]"##,
+            r##"["#+ff800"]"##,
+            "[[1, 2]]",
+            "[[1, 2, 256]]",
+            "{}",
+        ];
+        for bad in bad_palettes {
+            assert!(parse_json(bad.as_bytes()).is_err(), "{}", bad);
+        }
+        assert!(matches!(parse_json(b"[").unwrap_err(), ThemeError::Json(_)));
+    }
+}
@@ -0,0 +1,186 @@
+// Copyright 2024 Chris Ridd <chrisridd@mac.com>. All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use glob::{MatchOptions, Pattern};
+use regex::Regex;
+
+use crate::error::ThemeError;
+use crate::kind::ResourceKind;
+
+/// One way of matching resource names
+enum Matcher {
+    Glob(Pattern),
+    Regex(Regex),
+}
+
+/// Chooses resources by name and by kind
+///
+/// Names are matched with shell-style globs such as `arrow_*` or `*:4`, or
+/// with regular expressions which must match the whole name. A name always
+/// matches itself, even if it has glob characters in it. If no patterns are
+/// given every name matches, and if no kinds are given every kind does.
+#[derive(Default)]
+pub struct Selection {
+    patterns: Vec<(String, Matcher)>,
+    kinds: Vec<String>,
+}
+
+impl Selection {
+    pub fn new() -> Self {
+        Selection::default()
+    }
+
+    /// Select names matching a shell-style glob
+    pub fn glob(&mut self, pattern: &str) -> Result<&mut Self, ThemeError> {
+        let glob = Pattern::new(pattern)
+            .map_err(|e| ThemeError::Format(format!("Bad glob {:?}: {}", pattern, e)))?;
+        self.patterns.push((pattern.to_string(), Matcher::Glob(glob)));
+        Ok(self)
+    }
+
+    /// Select names matching a regular expression
+    pub fn regex(&mut self, pattern: &str) -> Result<&mut Self, ThemeError> {
+        let regex = Regex::new(&format!("^(?:{})$", pattern))
+            .map_err(|e| ThemeError::Format(format!("Bad regex {:?}: {}", pattern, e)))?;
+        self.patterns.push((pattern.to_string(), Matcher::Regex(regex)));
+        Ok(self)
+    }
+
+    /// Select resources of a kind, named as by [`ResourceKind::name`]
+    pub fn kind(&mut self, kind: &str) -> &mut Self {
+        self.kinds.push(kind.to_string());
+        self
+    }
+
+    /// Whether kinds are being selected, so the data must be looked at
+    pub fn has_kinds(&self) -> bool {
+        !self.kinds.is_empty()
+    }
+
+    pub fn matches_name(&self, name: &str) -> bool {
+        self.patterns.is_empty() || self.patterns.iter().any(|(p, m)| matches(p, m, name))
+    }
+
+    pub fn matches_kind(&self, kind: &ResourceKind) -> bool {
+        self.kinds.is_empty() || self.kinds.iter().any(|k| k == kind.name())
+    }
+
+    /// The patterns which match none of the names
+    pub fn unmatched<'a, I: IntoIterator<Item = &'a str>>(&self, names: I) -> Vec<&str> {
+        let names: Vec<&str> = names.into_iter().collect();
+        self.patterns
+            .iter()
+            .filter(|(p, m)| !names.iter().any(|name| matches(p, m, name)))
+            .map(|(p, _)| p.as_str())
+            .collect()
+    }
+}
+
+fn matches(pattern: &str, matcher: &Matcher, name: &str) -> bool {
+    // Names are not paths, so * must match a / too
+    let options = MatchOptions {
+        case_sensitive: true,
+        require_literal_separator: false,
+        require_literal_leading_dot: false,
+    };
+    pattern == name
+        || match matcher {
+            Matcher::Glob(glob) => glob.matches_with(name, options),
+            Matcher::Regex(regex) => regex.is_match(name),
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::theme::tests::header;
+
+    fn globs(patterns: &[&str]) -> Selection {
+        let mut selection = Selection::new();
+        for pattern in patterns {
+            selection.glob(pattern).unwrap();
+        }
+        selection
+    }
+
+    #[test]
+    fn everything_matches_nothing() {
+        let selection = Selection::new();
+        assert!(selection.matches_name("anything"));
+        assert!(selection.matches_name(""));
+        assert!(!selection.has_kinds());
+    }
+
+    #[test]
+    fn globs_match() {
+        let selection = globs(&["arrow_*", "*:4"]);
+        assert!(selection.matches_name("arrow_down"));
+        assert!(selection.matches_name("about:4"));
+        assert!(!selection.matches_name("about:5"));
+        // Names are not paths
+        assert!(globs(&["a*"]).matches_name("a/b"));
+        assert!(globs(&["*b"]).matches_name(".b"));
+        assert!(Selection::new().glob("[").is_err());
+    }
+
+    #[test]
+    fn names_match_themselves() {
+        let selection = globs(&["what[1]?"]);
+        assert!(selection.matches_name("what[1]?"));
+        assert!(!selection.matches_name("what[1]x"));
+    }
+
+    #[test]
+    fn regexes_match_whole_names() {
+        let mut selection = Selection::new();
+        selection.regex("arrow_(up|down):4").unwrap();
+        assert!(selection.matches_name("arrow_up:4"));
+        assert!(!selection.matches_name("arrow_up:40"));
+        assert!(!selection.matches_name("big_arrow_up:4"));
+        assert!(Selection::new().regex("(").is_err());
+        // Alternatives are anchored as a group
+        let mut selection = Selection::new();
+        selection.regex("a|b").unwrap();
+        assert!(!selection.matches_name("ab"));
+    }
+
+    #[test]
+    fn unmatched_patterns() {
+        let selection = globs(&["a*", "nope", "b"]);
+        assert_eq!(selection.unmatched(["abc", "b"]), ["nope"]);
+        assert!(selection.unmatched(["abc", "b", "nope"]).is_empty());
+    }
+
+    #[test]
+    fn kinds() {
+        let mut selection = Selection::new();
+        selection.kind("json");
+        assert!(selection.has_kinds());
+        let json = ResourceKind::kind_of(&header("layout", 100), b"{\"a\": 1}");
+        let other = ResourceKind::kind_of(&header("other", 100), b"something else");
+        assert!(selection.matches_kind(&json));
+        assert!(!selection.matches_kind(&other));
+    }
+}
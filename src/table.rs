@@ -0,0 +1,180 @@
+// Copyright 2024 Chris Ridd <chrisridd@mac.com>. All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::error::ThemeError;
+use crate::theme::{read_resource_header, ResourceHeader, HEADER_LEN_BIAS};
+
+/// Anything the resource table can be read from
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Reads the resource table of one version of the theme format
+///
+/// The reader is positioned just after the header length field.
+pub trait TableParser {
+    fn read_table(
+        &self,
+        reader: &mut dyn ReadSeek,
+        header_len: u32,
+    ) -> Result<Vec<ResourceHeader>, ThemeError>;
+}
+
+/// The parser for a version of the theme format
+///
+/// Versions we know nothing about get a best effort [`RawTable`] parser.
+pub fn parser_for(version: u8) -> Box<dyn TableParser> {
+    match version {
+        1 => Box::new(Version1),
+        _ => Box::new(RawTable),
+    }
+}
+
+/// Whether there is a real parser for a version of the theme format
+pub fn is_known_version(version: u8) -> bool {
+    version == 1
+}
+
+/// Version 1 themes, where the header length says where the table ends
+pub struct Version1;
+
+impl TableParser for Version1 {
+    fn read_table(
+        &self,
+        reader: &mut dyn ReadSeek,
+        header_len: u32,
+    ) -> Result<Vec<ResourceHeader>, ThemeError> {
+        let endpos = (reader.stream_position()? + header_len as u64)
+            .checked_sub(HEADER_LEN_BIAS as u64)
+            .ok_or_else(|| ThemeError::Format(String::from("Header length too small")))?;
+        let mut headers = vec![];
+        while reader.stream_position()? < endpos {
+            let header = read_resource_header(reader, headers.is_empty())?;
+            headers.push(header);
+        }
+        Ok(headers)
+    }
+}
+
+/// Best effort parser for unknown versions
+///
+/// This assumes the entries look like version 1 entries, but ignores the
+/// header length and keeps reading entries for as long as they make sense:
+/// each must point at data inside the file, and the table must end before
+/// the first resource's data starts.
+pub struct RawTable;
+
+impl TableParser for RawTable {
+    fn read_table(
+        &self,
+        reader: &mut dyn ReadSeek,
+        _header_len: u32,
+    ) -> Result<Vec<ResourceHeader>, ThemeError> {
+        let start = reader.stream_position()?;
+        let file_len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(start))?;
+
+        let mut headers = vec![];
+        let mut data_start = file_len;
+        while reader.stream_position()? + 12 <= data_start {
+            let header = match read_resource_header(reader, headers.is_empty()) {
+                Ok(header) => header,
+                Err(_) => break,
+            };
+            let table_end = reader.stream_position()?;
+            let offset = header.offset as u64;
+            let end = offset + header.compressed_size as u64;
+            if header.compressed_size == 0 || offset < table_end || end > file_len {
+                break;
+            }
+            data_start = data_start.min(offset);
+            if table_end > data_start {
+                break;
+            }
+            headers.push(header);
+        }
+        if headers.is_empty() {
+            return Err(ThemeError::Format(String::from(
+                "Could not find a resource table",
+            )));
+        }
+        Ok(headers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::theme::FINGERPRINT;
+    use crate::{Theme, ThemeWriter};
+
+    fn theme() -> Vec<u8> {
+        let mut writer = ThemeWriter::new();
+        writer.config(b"[theme]\n").unwrap();
+        writer.add("about:4", b"about").unwrap();
+        writer.add("b", b"bee").unwrap();
+        let mut bytes = Vec::new();
+        writer.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn unknown_version_is_read_raw() {
+        let mut bytes = theme();
+        bytes[FINGERPRINT.len()] = 7;
+        // The raw parser does not trust the header length
+        bytes[FINGERPRINT.len() + 1..][..4].copy_from_slice(&[0xff; 4]);
+        let theme = Theme::from_reader(Cursor::new(bytes)).unwrap();
+        assert_eq!(theme.version(), 7);
+        assert!(theme.is_raw());
+        let names: Vec<&str> = theme.resources().map(|res| res.name()).collect();
+        assert_eq!(names, ["", "about:4", "b"]);
+        assert_eq!(&*theme.resource("b").unwrap().data().unwrap(), b"bee");
+    }
+
+    #[test]
+    fn known_version_is_not_raw() {
+        assert!(is_known_version(1));
+        assert!(!is_known_version(2));
+        let theme = Theme::from_reader(Cursor::new(theme())).unwrap();
+        assert!(!theme.is_raw());
+    }
+
+    #[test]
+    fn no_table() {
+        let mut bytes = FINGERPRINT.to_vec();
+        bytes.push(7);
+        bytes.extend_from_slice(&[0xff; 40]);
+        match Theme::from_reader(Cursor::new(bytes)) {
+            Err(ThemeError::Format(message)) => assert_eq!(message, "Could not find a resource table"),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("found a table"),
+        }
+    }
+}
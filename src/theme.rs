@@ -0,0 +1,538 @@
+// Copyright 2024 Chris Ridd <chrisridd@mac.com>. All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, Write};
+use std::path::Path;
+use std::slice;
+
+use yazi::{decompress, Adler32, Decoder, Format};
+
+use crate::error::ThemeError;
+use crate::name;
+use crate::table;
+
+/// Every theme file starts with this fingerprint
+pub const FINGERPRINT: &[u8; 15] = b"PocketBookTheme";
+
+/// The theme format version we write
+pub const VERSION: u8 = 1;
+
+/// The resource table follows the fingerprint, version and header length
+pub(crate) const TABLE_OFFSET: u64 = 20;
+
+/// The header length field is this much larger than the resource table
+pub(crate) const HEADER_LEN_BIAS: u32 = 32;
+
+/// Entry in the theme's resource table
+#[derive(Clone, Debug)]
+pub struct ResourceHeader {
+    pub size: u32,            // offset 0
+    pub offset: u32,          // offset 4
+    pub compressed_size: u32, // offset 8
+    pub name: String,         // offset 12, see the name module for the encoding
+                              // extra NUL bytes padding to the nearest 4 bytes
+}
+
+/// A PocketBook theme file
+///
+/// The resource table is read when the theme is opened, but resource data is
+/// only read and decompressed when asked for.
+pub struct Theme<R> {
+    reader: RefCell<R>,
+    version: u8,
+    headers: Vec<ResourceHeader>,
+}
+
+impl Theme<BufReader<File>> {
+    /// Open a theme file and read its resource table
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ThemeError> {
+        let path = path.as_ref();
+        File::open(path)
+            .map_err(ThemeError::from)
+            .and_then(|file| Theme::from_reader(BufReader::new(file)))
+            .map_err(|e| e.with_theme(path))
+    }
+}
+
+impl<R: Read + Seek> Theme<R> {
+    /// Read the resource table of a theme from any seekable reader
+    pub fn from_reader(mut reader: R) -> Result<Self, ThemeError> {
+        let (version, headers) = read_versioned_headers(&mut reader)?;
+        Ok(Theme {
+            reader: RefCell::new(reader),
+            version,
+            headers,
+        })
+    }
+
+    /// The format version byte from the start of the theme
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Whether the resource table was read by guesswork, because the
+    /// format version is unknown
+    pub fn is_raw(&self) -> bool {
+        !table::is_known_version(self.version)
+    }
+
+    /// The resource table's entries, in table order
+    ///
+    /// Their data need not be in the same order in the file.
+    pub fn headers(&self) -> &[ResourceHeader] {
+        &self.headers
+    }
+
+    /// Iterate over the resources, in table order
+    pub fn resources(&self) -> Resources<'_, R> {
+        Resources {
+            theme: self,
+            headers: self.headers.iter(),
+        }
+    }
+
+    /// Find a resource by name; the configuration has the empty name
+    pub fn resource(&self, name: &str) -> Option<Resource<'_, R>> {
+        self.resources().find(|res| res.name() == name)
+    }
+
+    /// Read and decompress the data of a resource
+    pub fn read(&self, header: &ResourceHeader) -> Result<Box<[u8]>, ThemeError> {
+        read_resource(&mut *self.reader.borrow_mut(), header).map_err(|e| e.with_resource(header))
+    }
+
+    /// Read and decompress no more than the first `len` bytes of a resource
+    pub fn read_prefix(&self, header: &ResourceHeader, len: usize) -> Result<Vec<u8>, ThemeError> {
+        read_resource_prefix(&mut *self.reader.borrow_mut(), header, len)
+            .map_err(|e| e.with_resource(header))
+    }
+
+    /// Read the data of a resource without decompressing it
+    pub fn read_compressed(&self, header: &ResourceHeader) -> Result<Vec<u8>, ThemeError> {
+        read_compressed_resource(&mut *self.reader.borrow_mut(), header)
+            .map_err(|e| e.with_resource(header))
+    }
+
+    /// Give back the underlying reader
+    pub fn into_inner(self) -> R {
+        self.reader.into_inner()
+    }
+}
+
+/// Iterator over the resources of a theme
+pub struct Resources<'a, R> {
+    theme: &'a Theme<R>,
+    headers: slice::Iter<'a, ResourceHeader>,
+}
+
+impl<'a, R> Iterator for Resources<'a, R> {
+    type Item = Resource<'a, R>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.headers.next().map(|header| Resource {
+            theme: self.theme,
+            header,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.headers.size_hint()
+    }
+}
+
+impl<R> ExactSizeIterator for Resources<'_, R> {}
+
+/// A single resource in a theme
+pub struct Resource<'a, R> {
+    theme: &'a Theme<R>,
+    header: &'a ResourceHeader,
+}
+
+impl<R> Clone for Resource<'_, R> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<R> Copy for Resource<'_, R> {}
+
+impl<'a, R> Resource<'a, R> {
+    pub fn header(&self) -> &'a ResourceHeader {
+        self.header
+    }
+
+    pub fn name(&self) -> &'a str {
+        &self.header.name
+    }
+}
+
+impl<R: Read + Seek> Resource<'_, R> {
+    /// Read and decompress the data of this resource
+    pub fn data(&self) -> Result<Box<[u8]>, ThemeError> {
+        self.theme.read(self.header)
+    }
+
+    /// Read and decompress no more than the first `len` bytes of this resource
+    pub fn data_prefix(&self, len: usize) -> Result<Vec<u8>, ThemeError> {
+        self.theme.read_prefix(self.header, len)
+    }
+
+    /// Read the zlib-compressed data of this resource, exactly as stored
+    pub fn compressed_data(&self) -> Result<Vec<u8>, ThemeError> {
+        self.theme.read_compressed(self.header)
+    }
+}
+
+/// Read the fingerprint, version and resource table from the start of a theme
+pub fn read_headers<R: Read + Seek>(reader: &mut R) -> Result<Vec<ResourceHeader>, ThemeError> {
+    Ok(read_versioned_headers(reader)?.1)
+}
+
+/// Read the fingerprint and resource table, also returning the format version
+pub fn read_versioned_headers<R: Read + Seek>(
+    reader: &mut R,
+) -> Result<(u8, Vec<ResourceHeader>), ThemeError> {
+    let mut fingerprint = [0u8; 15];
+    reader.read_exact(&mut fingerprint)?;
+    if &fingerprint != FINGERPRINT {
+        return Err(ThemeError::Format(String::from(
+            "File does not start PocketBookTheme",
+        )));
+    }
+    let mut version = [0u8; 1];
+    reader.read_exact(&mut version)?;
+    let mut header_len = [0u8; 4];
+    reader.read_exact(&mut header_len)?;
+    let headers = table::parser_for(version[0]).read_table(reader, u32::from_le_bytes(header_len))?;
+
+    Ok((version[0], headers))
+}
+
+/// Read and decompress the data of a resource
+pub fn read_resource<R: Read + Seek>(
+    reader: &mut R,
+    header: &ResourceHeader,
+) -> Result<Box<[u8]>, ThemeError> {
+    let compressed = read_compressed_resource(reader, header)?;
+    inflate(&compressed)
+}
+
+/// Read and decompress the data of a resource using positional reads
+///
+/// Unlike [`read_resource`] this needs no seeking, so any number of threads
+/// can read resources from the same file at once.
+pub fn read_resource_at<F: ReadAt + ?Sized>(
+    source: &F,
+    header: &ResourceHeader,
+) -> Result<Box<[u8]>, ThemeError> {
+    let compressed = read_compressed_resource_at(source, header)?;
+    inflate(&compressed)
+}
+
+/// Read the compressed data of a resource using positional reads
+pub fn read_compressed_resource_at<F: ReadAt + ?Sized>(
+    source: &F,
+    header: &ResourceHeader,
+) -> Result<Vec<u8>, ThemeError> {
+    let mut compressed = vec![0u8; header.compressed_size as usize];
+    source.read_exact_at(&mut compressed, header.offset as u64)?;
+    Ok(compressed)
+}
+
+/// Decompress a resource's zlib stream, checking its Adler-32 checksum
+pub fn inflate(compressed: &[u8]) -> Result<Box<[u8]>, ThemeError> {
+    let (uncompressed, checksum) = inflate_unchecked(compressed)?;
+    if checksum != Some(Adler32::from_buf(&uncompressed).finish()) {
+        return Err(ThemeError::Zlib(yazi::Error::InvalidBitstream));
+    }
+    Ok(uncompressed)
+}
+
+/// Decompress a resource's zlib stream without checking it, also giving the
+/// checksum the stream ends with
+pub fn inflate_unchecked(compressed: &[u8]) -> Result<(Box<[u8]>, Option<u32>), ThemeError> {
+    match decompress(compressed, Format::Zlib) {
+        Ok((uncompressed, checksum)) => Ok((uncompressed.into_boxed_slice(), checksum)),
+        Err(e) => Err(ThemeError::Zlib(e)),
+    }
+}
+
+/// Sources that can be read at an offset without a shared file position
+pub trait ReadAt {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()>;
+}
+
+#[cfg(unix)]
+impl ReadAt for File {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        std::os::unix::fs::FileExt::read_exact_at(self, buf, offset)
+    }
+}
+
+#[cfg(windows)]
+impl ReadAt for File {
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        use std::os::windows::fs::FileExt;
+        while !buf.is_empty() {
+            match self.seek_read(buf, offset) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ReadAt for [u8] {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let start = usize::try_from(offset).unwrap_or(usize::MAX);
+        match self.get(start..).and_then(|rest| rest.get(..buf.len())) {
+            Some(bytes) => {
+                buf.copy_from_slice(bytes);
+                Ok(())
+            }
+            None => Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+}
+
+/// Read and decompress no more than the first `len` bytes of a resource
+///
+/// Only as much compressed data is read and inflated as is needed, which is
+/// far cheaper than [`read_resource`] for looking at the start of big bitmaps.
+pub fn read_resource_prefix<R: Read + Seek>(
+    reader: &mut R,
+    header: &ResourceHeader,
+    len: usize,
+) -> Result<Vec<u8>, ThemeError> {
+    seek_to(reader, header.offset as u64)?;
+    let mut prefix = Prefix {
+        bytes: Vec::with_capacity(len),
+        len,
+    };
+    let mut decoder = Decoder::new();
+    decoder.set_format(Format::Zlib);
+    let mut stream = decoder.stream(&mut prefix);
+    let mut remaining = header.compressed_size as usize;
+    let mut chunk = [0u8; 256];
+    while remaining > 0 {
+        let n = remaining.min(chunk.len());
+        reader.read_exact(&mut chunk[..n])?;
+        remaining -= n;
+        if let Err(e) = stream.write(&chunk[..n]) {
+            // The prefix refuses more bytes once it is full
+            match e {
+                yazi::Error::Io(ref io) if io.kind() == io::ErrorKind::WriteZero => break,
+                e => return Err(ThemeError::Zlib(e)),
+            }
+        }
+    }
+    if remaining == 0 {
+        match stream.finish() {
+            Ok(_) => {}
+            Err(yazi::Error::Io(ref io)) if io.kind() == io::ErrorKind::WriteZero => {}
+            Err(e) => return Err(ThemeError::Zlib(e)),
+        }
+    } else {
+        drop(stream);
+    }
+    Ok(prefix.bytes)
+}
+
+/// A writer that takes bytes until it has enough
+struct Prefix {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl Write for Prefix {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.len - self.bytes.len());
+        self.bytes.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Seek only if not already there, so sequential reads keep their buffer
+fn seek_to<R: Read + Seek>(reader: &mut R, offset: u64) -> Result<(), ThemeError> {
+    if reader.stream_position()? != offset {
+        reader.seek(io::SeekFrom::Start(offset))?;
+    }
+    Ok(())
+}
+
+/// Read the compressed data of a resource
+pub fn read_compressed_resource<R: Read + Seek>(
+    reader: &mut R,
+    header: &ResourceHeader,
+) -> Result<Vec<u8>, ThemeError> {
+    seek_to(reader, header.offset as u64)?;
+    let mut compressed = vec![0u8; header.compressed_size as usize];
+    reader.read_exact(&mut compressed)?;
+    Ok(compressed)
+}
+
+/// Read one entry of the resource table; the first entry has no name
+pub fn read_resource_header<R: Read + ?Sized>(
+    reader: &mut R,
+    first: bool,
+) -> Result<ResourceHeader, ThemeError> {
+    let mut size = [0u8; 4];
+    let mut unknown = [0u8; 4];
+    let mut compressed_size = [0u8; 4];
+    reader.read_exact(&mut size)?;
+    reader.read_exact(&mut unknown)?;
+    reader.read_exact(&mut compressed_size)?;
+    let mut name = Vec::new();
+    if !first {
+        loop {
+            let mut chars = [0u8; 4];
+            reader.read_exact(&mut chars)?;
+            match chars.iter().position(|&c| c == 0) {
+                Some(nul) => {
+                    name.extend_from_slice(&chars[..nul]);
+                    break;
+                }
+                None => name.extend_from_slice(&chars),
+            }
+        }
+    }
+    Ok(ResourceHeader {
+        size: u32::from_le_bytes(size),
+        offset: u32::from_le_bytes(unknown),
+        compressed_size: u32::from_le_bytes(compressed_size),
+        name: name::decode(&name),
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::kind::KIND_PREFIX_LEN;
+    use crate::ThemeWriter;
+
+    /// A table entry for tests that only look at names and sizes
+    pub(crate) fn header(name: &str, size: u32) -> ResourceHeader {
+        ResourceHeader {
+            size,
+            offset: 0,
+            compressed_size: 0,
+            name: String::from(name),
+        }
+    }
+
+    /// Bytes that hardly compress, so the compressed data is big too
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 1u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn theme(data: &[u8]) -> Cursor<Vec<u8>> {
+        let mut writer = ThemeWriter::new();
+        writer.config(b"[theme]\n").unwrap();
+        writer.add("big", data).unwrap();
+        let mut bytes = Vec::new();
+        writer.write(&mut bytes).unwrap();
+        Cursor::new(bytes)
+    }
+
+    #[test]
+    fn prefix_stops_early() {
+        let data = noise(100_000);
+        let mut reader = theme(&data);
+        let header = read_headers(&mut reader).unwrap()[1].clone();
+        assert!(header.compressed_size > 50_000);
+
+        let prefix = read_resource_prefix(&mut reader, &header, KIND_PREFIX_LEN).unwrap();
+        assert_eq!(prefix, data[..KIND_PREFIX_LEN]);
+        let read = reader.position() - header.offset as u64;
+        assert!(read < 4096, "read {} compressed bytes for the prefix", read);
+    }
+
+    #[test]
+    fn positional_reads() {
+        let data = noise(10_000);
+        let bytes = theme(&data).into_inner();
+        let headers = read_headers(&mut Cursor::new(&bytes)).unwrap();
+        assert_eq!(&*read_resource_at(&bytes[..], &headers[0]).unwrap(), b"[theme]\n");
+        assert_eq!(&*read_resource_at(&bytes[..], &headers[1]).unwrap(), &data[..]);
+
+        let mut past_end = headers[1].clone();
+        past_end.offset += 1;
+        // Like the other free functions, callers say which resource it was
+        let error = read_compressed_resource_at(&bytes[..], &past_end).unwrap_err();
+        assert!(error.context().is_none());
+    }
+
+    #[test]
+    fn checksums_are_checked() {
+        let mut bytes = theme(b"checked").into_inner();
+        let header = read_headers(&mut Cursor::new(&bytes)).unwrap()[1].clone();
+        let end = (header.offset + header.compressed_size) as usize;
+        bytes[end - 1] ^= 1;
+        assert!(read_resource_at(&bytes[..], &header).is_err());
+        let compressed = read_compressed_resource_at(&bytes[..], &header).unwrap();
+        let (data, checksum) = inflate_unchecked(&compressed).unwrap();
+        assert_eq!(&*data, b"checked");
+        assert!(checksum.is_some());
+    }
+
+    #[test]
+    fn slices_read_at_an_offset() {
+        let bytes: &[u8] = b"0123456789";
+        let mut buf = [0u8; 3];
+        bytes.read_exact_at(&mut buf, 7).unwrap();
+        assert_eq!(&buf, b"789");
+        assert!(bytes.read_exact_at(&mut buf, 8).is_err());
+        assert!(bytes.read_exact_at(&mut buf, u64::MAX).is_err());
+        bytes.read_exact_at(&mut [], 10).unwrap();
+    }
+
+    #[test]
+    fn prefix_of_small_resource_is_all_of_it() {
+        let mut reader = theme(b"small");
+        let header = read_headers(&mut reader).unwrap()[1].clone();
+        assert_eq!(read_resource_prefix(&mut reader, &header, KIND_PREFIX_LEN).unwrap(), b"small");
+        assert_eq!(read_resource_prefix(&mut reader, &header, 2).unwrap(), b"sm");
+    }
+}
@@ -0,0 +1,338 @@
+// Copyright 2024 Chris Ridd <chrisridd@mac.com>. All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Seek, SeekFrom};
+
+use yazi::Adler32;
+
+use crate::error::ThemeError;
+use crate::table::is_known_version;
+use crate::theme::{inflate_unchecked, read_compressed_resource, read_versioned_headers, TABLE_OFFSET};
+
+/// Something wrong with a theme file
+#[derive(Debug)]
+pub enum Problem {
+    /// The format version is unknown, so the table was read by guesswork
+    UnknownVersion(u8),
+    /// The compressed data does not fit inside the file
+    OutOfBounds { offset: u32, compressed_size: u32, file_len: u64 },
+    /// The compressed data starts inside the resource table
+    OverlapsTable { offset: u32, table_end: u64 },
+    /// The compressed data overlaps another resource's
+    Overlaps { other: String },
+    /// The NUL padding after the name has other bytes in it
+    NamePadding,
+    /// The compressed data could not be decompressed
+    Decompress(String),
+    /// The decompressed data does not match the stream's Adler-32 checksum
+    Checksum { expected: Option<u32>, actual: u32 },
+    /// The decompressed data is not the size the table says
+    SizeMismatch { expected: u32, actual: usize },
+    /// Bytes between the table and the last resource that nothing refers to
+    Unreferenced { start: u64, end: u64 },
+    /// Bytes after the last resource
+    TrailingData { start: u64, end: u64 },
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::UnknownVersion(version) => write!(
+                f,
+                "unknown format version {}, the resource table may be wrong",
+                version
+            ),
+            Problem::OutOfBounds { offset, compressed_size, file_len } => write!(
+                f,
+                "data at {} with compressed size {} runs past end of file at {}",
+                offset, compressed_size, file_len
+            ),
+            Problem::OverlapsTable { offset, table_end } => write!(
+                f,
+                "data at {} starts inside the resource table, which ends at {}",
+                offset, table_end
+            ),
+            Problem::Overlaps { other } => write!(f, "data overlaps resource {:?}", other),
+            Problem::NamePadding => write!(f, "name padding is not all NUL bytes"),
+            Problem::Decompress(e) => write!(f, "data does not decompress: {}", e),
+            Problem::Checksum { expected: Some(expected), actual } => write!(
+                f,
+                "decompressed data has Adler-32 checksum {:08x} but the stream says {:08x}",
+                actual, expected
+            ),
+            Problem::Checksum { expected: None, .. } => {
+                write!(f, "data has no Adler-32 checksum")
+            }
+            Problem::SizeMismatch { expected, actual } => write!(
+                f,
+                "decompressed size is {} but the table says {}",
+                actual, expected
+            ),
+            Problem::Unreferenced { start, end } => write!(
+                f,
+                "bytes {} to {} are not used by any resource",
+                start, end
+            ),
+            Problem::TrailingData { start, end } => write!(
+                f,
+                "{} bytes of trailing data after the last resource at {}",
+                end - start, start
+            ),
+        }
+    }
+}
+
+/// A problem, and the resource it belongs to if any
+#[derive(Debug)]
+pub struct Issue {
+    /// Index and name of the resource in the table
+    pub resource: Option<(usize, String)>,
+    pub problem: Problem,
+}
+
+/// Check the structure of a theme and every resource in it
+///
+/// An error is only returned when the resource table itself cannot be read;
+/// everything else is reported as an [`Issue`].
+pub fn verify<R: Read + Seek>(reader: &mut R) -> Result<Vec<Issue>, ThemeError> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let (version, headers) = read_versioned_headers(reader)?;
+    let table_end = reader.stream_position()?;
+
+    let mut table = vec![0u8; (table_end - TABLE_OFFSET) as usize];
+    reader.seek(SeekFrom::Start(TABLE_OFFSET))?;
+    reader.read_exact(&mut table)?;
+
+    let mut issues = Vec::new();
+    let mut issue = |i: Option<usize>, problem: Problem| {
+        issues.push(Issue {
+            resource: i.map(|i| (i, headers[i].name.clone())),
+            problem,
+        })
+    };
+
+    if !is_known_version(version) {
+        issue(None, Problem::UnknownVersion(version));
+    }
+
+    // Names are read 4 bytes at a time up to the first NUL, so the rest of
+    // that last group of 4 is padding.
+    let mut pos = 0usize;
+    for i in 0..headers.len() {
+        pos += 12;
+        if i > 0 {
+            let len = table[pos..].iter().position(|&c| c == 0).unwrap_or(table.len() - pos);
+            let end = (pos + (len / 4 + 1) * 4).min(table.len());
+            if table[pos + len..end].iter().any(|&c| c != 0) {
+                issue(Some(i), Problem::NamePadding);
+            }
+            pos = end;
+        }
+    }
+
+    let mut ranges = Vec::new();
+    for (i, header) in headers.iter().enumerate() {
+        let end = header.offset as u64 + header.compressed_size as u64;
+        if end > file_len {
+            issue(
+                Some(i),
+                Problem::OutOfBounds {
+                    offset: header.offset,
+                    compressed_size: header.compressed_size,
+                    file_len,
+                },
+            );
+            // What there is of its data is still its own, not unreferenced
+            if (header.offset as u64) < file_len {
+                ranges.push((header.offset as u64, file_len, i));
+            }
+            continue;
+        }
+        if (header.offset as u64) < table_end {
+            issue(
+                Some(i),
+                Problem::OverlapsTable {
+                    offset: header.offset,
+                    table_end,
+                },
+            );
+        }
+        ranges.push((header.offset as u64, end, i));
+
+        let compressed = read_compressed_resource(reader, header)?;
+        match inflate_unchecked(&compressed) {
+            Err(ThemeError::Zlib(e)) => issue(Some(i), Problem::Decompress(format!("{:?}", e))),
+            Err(e) => return Err(e),
+            Ok((data, checksum)) => {
+                let actual = Adler32::from_buf(&data).finish();
+                if checksum != Some(actual) {
+                    issue(Some(i), Problem::Checksum { expected: checksum, actual });
+                }
+                if data.len() != header.size as usize {
+                    issue(
+                        Some(i),
+                        Problem::SizeMismatch {
+                            expected: header.size,
+                            actual: data.len(),
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    // Walk the data in file order looking for gaps and overlaps
+    ranges.sort();
+    let mut cursor = table_end;
+    let mut last: Option<(u64, usize)> = None;
+    for &(start, end, i) in &ranges {
+        if let Some((last_end, last_i)) = last {
+            if start < last_end {
+                issue(
+                    Some(i),
+                    Problem::Overlaps {
+                        other: headers[last_i].name.clone(),
+                    },
+                );
+            }
+        }
+        if start > cursor {
+            issue(None, Problem::Unreferenced { start: cursor, end: start });
+        }
+        if last.is_none_or(|(last_end, _)| end > last_end) {
+            last = Some((end, i));
+        }
+        cursor = cursor.max(end);
+    }
+    if cursor < file_len {
+        issue(None, Problem::TrailingData { start: cursor, end: file_len });
+    }
+
+    Ok(issues)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::theme::{read_headers, ResourceHeader};
+    use crate::ThemeWriter;
+
+    /// Table entries of the resources, after the configuration's
+    const ONE: usize = TABLE_OFFSET as usize + 12;
+    const TWO: usize = ONE + 16;
+
+    fn theme() -> Vec<u8> {
+        let mut writer = ThemeWriter::new();
+        writer.config(b"[theme]\n").unwrap();
+        writer.add("one", b"first resource").unwrap();
+        writer.add("tw", b"other resource").unwrap();
+        let mut bytes = Vec::new();
+        writer.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn problems(bytes: Vec<u8>) -> Vec<Problem> {
+        verify(&mut Cursor::new(bytes))
+            .unwrap()
+            .into_iter()
+            .map(|issue| issue.problem)
+            .collect()
+    }
+
+    fn header(bytes: &[u8], i: usize) -> ResourceHeader {
+        read_headers(&mut Cursor::new(bytes)).unwrap()[i].clone()
+    }
+
+    #[test]
+    fn good_theme() {
+        assert!(problems(theme()).is_empty());
+    }
+
+    #[test]
+    fn corrupt_data() {
+        let mut bytes = theme();
+        let header = header(&bytes, 2);
+        bytes[header.offset as usize + 2] = 0xff;
+        assert!(matches!(
+            problems(bytes)[..],
+            [Problem::Decompress(_) | Problem::SizeMismatch { .. } | Problem::Checksum { .. }, ..]
+        ));
+    }
+
+    #[test]
+    fn checksum() {
+        let mut bytes = theme();
+        let header = header(&bytes, 2);
+        // The Adler-32 checksum ends the zlib stream
+        bytes[(header.offset + header.compressed_size) as usize - 1] ^= 1;
+        assert!(matches!(
+            problems(bytes)[..],
+            [Problem::Checksum { expected: Some(_), .. }]
+        ));
+    }
+
+    #[test]
+    fn size_mismatch() {
+        let mut bytes = theme();
+        bytes[TABLE_OFFSET as usize] += 1;
+        assert!(matches!(problems(bytes)[..], [Problem::SizeMismatch { .. }]));
+    }
+
+    #[test]
+    fn name_padding() {
+        let mut bytes = theme();
+        assert_eq!(&bytes[TWO + 12..TWO + 16], b"tw\0\0");
+        bytes[TWO + 15] = b'x';
+        assert!(matches!(problems(bytes)[..], [Problem::NamePadding]));
+    }
+
+    #[test]
+    fn overlaps() {
+        let mut bytes = theme();
+        let one = header(&bytes, 1);
+        bytes[TWO + 4..TWO + 8].copy_from_slice(&one.offset.to_le_bytes());
+        let problems = problems(bytes);
+        assert!(problems.iter().any(|problem| matches!(problem, Problem::Overlaps { other } if other == "one")));
+        assert!(problems.iter().any(|problem| matches!(problem, Problem::TrailingData { .. })));
+    }
+
+    #[test]
+    fn trailing_data() {
+        let mut bytes = theme();
+        bytes.extend_from_slice(b"junk");
+        assert!(matches!(problems(bytes)[..], [Problem::TrailingData { .. }]));
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        let mut bytes = theme();
+        bytes.truncate(bytes.len() - 1);
+        assert!(matches!(problems(bytes)[..], [Problem::OutOfBounds { .. }]));
+    }
+}
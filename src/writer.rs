@@ -0,0 +1,405 @@
+// Copyright 2024 Chris Ridd <chrisridd@mac.com>. All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::collections::HashSet;
+use std::io::Write;
+
+use yazi::{compress, CompressionLevel, Format};
+
+use crate::error::ThemeError;
+use crate::name;
+use crate::theme::{FINGERPRINT, HEADER_LEN_BIAS, TABLE_OFFSET, VERSION};
+
+/// A resource waiting to be written, already compressed
+struct Entry {
+    name: String,
+    /// The name as stored in the table
+    encoded: Vec<u8>,
+    size: u32,
+    compressed: Vec<u8>,
+}
+
+/// Builds a PocketBook theme file from resources
+///
+/// The configuration is always written as the first, nameless, resource and
+/// the other resources follow in the order they were added. Names are encoded
+/// as described in the [`name`](crate::name) module.
+///
+/// ```no_run
+/// use std::fs::File;
+/// use pbtools::ThemeWriter;
+///
+/// let mut writer = ThemeWriter::new();
+/// writer.config(b"[theme]\n")?;
+/// writer.add("about:4", &std::fs::read("about:4")?)?;
+/// writer.write(&mut File::create("Custom.pbt")?)?;
+/// # Ok::<(), pbtools::ThemeError>(())
+/// ```
+pub struct ThemeWriter {
+    level: CompressionLevel,
+    config: Option<Entry>,
+    entries: Vec<Entry>,
+    names: HashSet<Vec<u8>>,
+    layout: Option<Layout>,
+}
+
+/// Where to put the data of each resource, instead of one after another
+struct Layout {
+    /// Offsets of the data, configuration first
+    offsets: Vec<u32>,
+    /// Bytes for the gaps between the data and for after it
+    filler: Vec<u8>,
+}
+
+impl Default for ThemeWriter {
+    fn default() -> Self {
+        ThemeWriter::new()
+    }
+}
+
+impl ThemeWriter {
+    pub fn new() -> Self {
+        ThemeWriter {
+            level: CompressionLevel::Default,
+            config: None,
+            entries: vec![],
+            names: HashSet::new(),
+            layout: None,
+        }
+    }
+
+    /// Set the zlib compression level for resources added after this
+    pub fn level(&mut self, level: CompressionLevel) -> &mut Self {
+        self.level = level;
+        self
+    }
+
+    /// Set the theme configuration
+    pub fn config(&mut self, data: &[u8]) -> Result<&mut Self, ThemeError> {
+        let compressed = compress(data, Format::Zlib, self.level)?;
+        self.config_compressed(data_size(data)?, compressed)
+    }
+
+    /// Set the theme configuration from already compressed data
+    pub fn config_compressed(
+        &mut self,
+        size: u32,
+        compressed: Vec<u8>,
+    ) -> Result<&mut Self, ThemeError> {
+        self.config = Some(Entry {
+            name: String::new(),
+            encoded: Vec::new(),
+            size,
+            compressed,
+        });
+        Ok(self)
+    }
+
+    /// Add a named resource, compressing it
+    pub fn add(&mut self, name: &str, data: &[u8]) -> Result<&mut Self, ThemeError> {
+        let compressed = compress(data, Format::Zlib, self.level)?;
+        self.add_compressed(name, data_size(data)?, compressed)
+    }
+
+    /// Add a named resource from already compressed data
+    ///
+    /// `size` is the length of the data once decompressed.
+    pub fn add_compressed(
+        &mut self,
+        name: &str,
+        size: u32,
+        compressed: Vec<u8>,
+    ) -> Result<&mut Self, ThemeError> {
+        if name.is_empty() {
+            return Err(ThemeError::Format(String::from(
+                "Only the configuration may have an empty name",
+            )));
+        }
+        let encoded = name::encode(name);
+        if encoded.contains(&0) {
+            return Err(ThemeError::Format(format!(
+                "Resource name {:?} contains a NUL",
+                name
+            )));
+        }
+        if !self.names.insert(encoded.clone()) {
+            return Err(ThemeError::Format(format!(
+                "Duplicate resource name {:?}",
+                name
+            )));
+        }
+        self.entries.push(Entry {
+            name: name.to_string(),
+            encoded,
+            size,
+            compressed,
+        });
+        Ok(self)
+    }
+
+    /// Put the data of each resource at a given offset, configuration first
+    ///
+    /// Anything between or after the data, up to the end of `filler`, comes
+    /// from the same place in `filler`. Given an original theme file and the
+    /// offsets from its table, this lays the data out exactly as it was.
+    pub fn layout(&mut self, offsets: Vec<u32>, filler: Vec<u8>) -> &mut Self {
+        self.layout = Some(Layout { offsets, filler });
+        self
+    }
+
+    /// Write the complete theme
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), ThemeError> {
+        let config = match &self.config {
+            Some(config) => config,
+            None => {
+                return Err(ThemeError::Format(String::from(
+                    "Theme has no configuration",
+                )))
+            }
+        };
+        let entries = || std::iter::once(config).chain(self.entries.iter());
+
+        // The first entry has no name at all, the others have their name
+        // followed by at least one NUL, padded to a multiple of 4 bytes.
+        let table_len: usize = 12 + self
+            .entries
+            .iter()
+            .map(|entry| 12 + padded_len(&entry.encoded))
+            .sum::<usize>();
+        let header_len = u32::try_from(table_len)
+            .ok()
+            .and_then(|len| len.checked_add(HEADER_LEN_BIAS))
+            .ok_or_else(|| ThemeError::Format(String::from("Resource table too big")))?;
+
+        let table_end = TABLE_OFFSET + table_len as u64;
+        let offsets = match &self.layout {
+            Some(layout) => layout.offsets(table_end, 1 + self.entries.len())?,
+            None => entries()
+                .scan(table_end, |offset, entry| {
+                    let start = *offset;
+                    *offset += entry.compressed.len() as u64;
+                    Some(u32::try_from(start))
+                })
+                .collect::<Result<_, _>>()
+                .map_err(|_| ThemeError::Format(String::from("Theme too big")))?,
+        };
+
+        let mut table = Vec::with_capacity(table_end as usize);
+        table.extend_from_slice(FINGERPRINT);
+        table.push(VERSION);
+        table.extend_from_slice(&header_len.to_le_bytes());
+        for (i, (entry, offset)) in entries().zip(&offsets).enumerate() {
+            let compressed_size = u32::try_from(entry.compressed.len())
+                .map_err(|_| ThemeError::Format(format!("Resource {:?} too big", entry.name)))?;
+            table.extend_from_slice(&entry.size.to_le_bytes());
+            table.extend_from_slice(&offset.to_le_bytes());
+            table.extend_from_slice(&compressed_size.to_le_bytes());
+            if i > 0 {
+                let mut name = entry.encoded.clone();
+                name.resize(padded_len(&entry.encoded), 0);
+                table.extend_from_slice(&name);
+            }
+        }
+
+        let Some(layout) = &self.layout else {
+            writer.write_all(&table)?;
+            for entry in entries() {
+                writer.write_all(&entry.compressed)?;
+            }
+            return Ok(());
+        };
+        let mut bytes = layout.filler.clone();
+        for (entry, &offset) in entries().zip(&offsets) {
+            let end = offset as usize + entry.compressed.len();
+            if bytes.len() < end {
+                bytes.resize(end, 0);
+            }
+            bytes[offset as usize..end].copy_from_slice(&entry.compressed);
+        }
+        // Resources may share data, but only if it is the same
+        for (entry, &offset) in entries().zip(&offsets) {
+            if bytes[offset as usize..][..entry.compressed.len()] != entry.compressed[..] {
+                return Err(ThemeError::Format(format!(
+                    "The data of resource {:?} overlaps another resource",
+                    entry.name
+                )));
+            }
+        }
+        bytes[..table.len()].copy_from_slice(&table);
+        writer.write_all(&bytes)?;
+        Ok(())
+    }
+}
+
+impl Layout {
+    /// The offsets, checked against the resources and the table
+    fn offsets(&self, table_end: u64, count: usize) -> Result<Vec<u32>, ThemeError> {
+        if self.offsets.len() != count {
+            return Err(ThemeError::Format(format!(
+                "A layout for {} resources cannot be used for {}",
+                self.offsets.len(),
+                count
+            )));
+        }
+        if let Some(offset) = self.offsets.iter().find(|&&offset| (offset as u64) < table_end) {
+            return Err(ThemeError::Format(format!(
+                "Resource data at offset {} would be inside the table, which ends at {}",
+                offset, table_end
+            )));
+        }
+        Ok(self.offsets.clone())
+    }
+}
+
+/// Length of a name in the resource table, including its NUL padding
+fn padded_len(name: &[u8]) -> usize {
+    (name.len() / 4 + 1) * 4
+}
+
+fn data_size(data: &[u8]) -> Result<u32, ThemeError> {
+    u32::try_from(data.len()).map_err(|_| ThemeError::Format(String::from("Resource too big")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::Theme;
+
+    fn written(writer: &ThemeWriter) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        writer.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn resources(bytes: Vec<u8>) -> Vec<(String, Vec<u8>)> {
+        let theme = Theme::from_reader(Cursor::new(bytes)).unwrap();
+        theme
+            .resources()
+            .map(|res| (res.name().to_string(), res.data().unwrap().into_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut writer = ThemeWriter::new();
+        writer.config(b"[theme]\n").unwrap();
+        writer.add("about:4", b"about").unwrap();
+        // Names of every length modulo 4, so each amount of padding
+        writer.add("abc", b"").unwrap();
+        writer.add("abcd", &[0xff; 1000]).unwrap();
+        writer.add("abcde", b"five").unwrap();
+        writer.add("a\\xffb\\\\", b"escaped").unwrap();
+        assert_eq!(
+            resources(written(&writer)),
+            [
+                (String::new(), b"[theme]\n".to_vec()),
+                (String::from("about:4"), b"about".to_vec()),
+                (String::from("abc"), Vec::new()),
+                (String::from("abcd"), vec![0xff; 1000]),
+                (String::from("abcde"), b"five".to_vec()),
+                (String::from("a\\xffb\\\\"), b"escaped".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn compressed_data_is_kept() {
+        let compressed = compress(b"data", Format::Zlib, CompressionLevel::BestSize).unwrap();
+        let mut writer = ThemeWriter::new();
+        writer.config(b"").unwrap();
+        writer.add_compressed("res", 4, compressed.clone()).unwrap();
+        let bytes = written(&writer);
+        let theme = Theme::from_reader(Cursor::new(bytes.clone())).unwrap();
+        let header = &theme.headers()[1];
+        assert_eq!(header.size, 4);
+        assert_eq!(
+            bytes[header.offset as usize..][..header.compressed_size as usize],
+            compressed[..]
+        );
+        assert_eq!(&*theme.read(header).unwrap(), b"data");
+    }
+
+    #[test]
+    fn layouts_are_kept() {
+        let mut writer = ThemeWriter::new();
+        writer.config(b"[theme]\n").unwrap();
+        writer.add("one", b"first").unwrap();
+        writer.add("two", b"second").unwrap();
+        let packed = written(&writer);
+        let headers = Theme::from_reader(Cursor::new(packed.clone())).unwrap().headers().to_vec();
+        let table_end = headers[0].offset;
+
+        // Data in reverse order, with a gap before each and junk at the end
+        let mut offsets = vec![0; 3];
+        let mut offset = table_end + 3;
+        for i in (0..3).rev() {
+            offsets[i] = offset;
+            offset += headers[i].compressed_size + 5;
+        }
+        let filler = vec![0xaa; offset as usize + 7];
+        writer.layout(offsets.clone(), filler.clone());
+        let bytes = written(&writer);
+        assert_eq!(bytes.len(), filler.len());
+        assert_eq!(bytes[table_end as usize..][..3], [0xaa; 3]);
+        assert_eq!(bytes[offset as usize - 5..], [0xaa; 12]);
+        let theme = Theme::from_reader(Cursor::new(bytes.clone())).unwrap();
+        let laid_out: Vec<u32> = theme.headers().iter().map(|header| header.offset).collect();
+        assert_eq!(laid_out, offsets);
+        assert_eq!(resources(bytes.clone()), resources(packed));
+
+        // Laying it out again as it is changes nothing
+        writer.layout(offsets, bytes.clone());
+        assert_eq!(written(&writer), bytes);
+    }
+
+    #[test]
+    fn bad_layouts() {
+        let mut writer = ThemeWriter::new();
+        writer.config(b"[theme]\n").unwrap();
+        writer.add("one", b"first").unwrap();
+        let end = written(&writer).len() as u32;
+        writer.layout(vec![end], Vec::new());
+        assert!(writer.write(&mut Vec::new()).is_err(), "too few offsets");
+        writer.layout(vec![end, 8], Vec::new());
+        assert!(writer.write(&mut Vec::new()).is_err(), "inside the table");
+        writer.layout(vec![end, end + 1], Vec::new());
+        assert!(writer.write(&mut Vec::new()).is_err(), "overlapping");
+        writer.layout(vec![end, end], Vec::new());
+        assert!(writer.write(&mut Vec::new()).is_err(), "different data shared");
+    }
+
+    #[test]
+    fn bad_names() {
+        let mut writer = ThemeWriter::new();
+        assert!(writer.add("", b"").is_err());
+        assert!(writer.add("a\0", b"").is_err());
+        assert!(writer.add("a\\x00", b"").is_err());
+        writer.add("a", b"").unwrap();
+        assert!(writer.add("a", b"").is_err());
+        assert!(writer.write(&mut Vec::new()).is_err(), "no configuration");
+    }
+}